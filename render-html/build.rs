@@ -0,0 +1,54 @@
+use std::{
+    env,
+    fmt::Write,
+    fs,
+    path::{Path, PathBuf},
+};
+
+/// The folders under `src/asset` that `fetch-assets.sh` fills with fonts and stylesheets
+const BUNDLED_FOLDERS: [&str; 3] = ["katex", "lucide", "inter"];
+
+/// Embeds the fonts and stylesheets committed under `src/asset`, so that self-contained
+/// output can inline them. Only the kinds of file the renderer inlines are embedded, so
+/// `katex.min.js`, which is included on its own, isn't in the binary twice.
+fn main() {
+    let assets = Path::new(env!("CARGO_MANIFEST_DIR")).join("src/asset");
+    // Watching a path that doesn't exist would rebuild every time, so watch the folder
+    // they all live in. Cargo scans directories recursively, which covers their files.
+    println!("cargo:rerun-if-changed={}", assets.to_string_lossy());
+
+    let mut files = Vec::new();
+    for folder in BUNDLED_FOLDERS {
+        collect_files(&assets.join(folder), &mut files);
+    }
+    files.retain(|file| {
+        file.extension()
+            .is_some_and(|ext| ext == "css" || ext == "woff2" || ext == "ttf")
+    });
+    files.sort();
+
+    let mut generated = String::from("pub const BUNDLED_ASSETS: &[(&str, &[u8])] = &[\n");
+    for file in files {
+        let name = file.strip_prefix(&assets).unwrap().to_string_lossy();
+        writeln!(generated, "    ({name:?}, include_bytes!({file:?})),").unwrap();
+    }
+    generated += "];\n";
+
+    let out = PathBuf::from(env::var("OUT_DIR").unwrap()).join("bundled_assets.rs");
+    fs::write(out, generated).unwrap();
+}
+
+fn collect_files(folder: &Path, files: &mut Vec<PathBuf>) {
+    let Ok(entries) = folder.read_dir() else {
+        return;
+    };
+
+    for entry in entries.flatten() {
+        let path = entry.path();
+        if path.is_dir() {
+            collect_files(&path, files);
+        } else if !entry.file_name().to_string_lossy().starts_with('.') {
+            files.push(path);
+        }
+    }
+}
@@ -0,0 +1,32 @@
+#! /bin/bash
+
+# Downloads the fonts and stylesheets that self-contained output inlines into
+# src/asset/katex, src/asset/lucide and src/asset/inter. Commit what it downloads:
+# builds without them still work, but default to loading everything from CDNs.
+
+set -e
+
+asset="$(dirname "$(realpath "$0")")/src/asset"
+tmp="$(mktemp -d)"
+trap 'rm -rf "$tmp"' EXIT
+
+# KaTeX has to match the version the katex crate typesets static math with, which is
+# also the version of the committed katex.min.js
+KATEX_VERSION="0.16.4"
+LUCIDE_VERSION="0.263.1"
+INTER_VERSION="4.0"
+
+rm -rf "$asset/katex/fonts" "$asset/katex/katex.min.css" "$asset/lucide" "$asset/inter"
+mkdir -p "$asset/katex/fonts" "$asset/lucide" "$asset/inter"
+
+curl -fL "https://github.com/KaTeX/KaTeX/releases/download/v$KATEX_VERSION/katex.tar.gz" |
+    tar -x -z -C "$tmp" -f -
+cp "$tmp/katex/katex.min.css" "$asset/katex/"
+# woff2 is all Chrome needs; the older formats would only bloat the binary
+cp "$tmp"/katex/fonts/*.woff2 "$asset/katex/fonts/"
+
+curl -fL -o "$asset/lucide/Lucide.ttf" "https://unpkg.com/lucide-static@$LUCIDE_VERSION/font/Lucide.ttf"
+curl -fL -o "$asset/lucide/LICENSE" "https://unpkg.com/lucide-static@$LUCIDE_VERSION/LICENSE"
+
+curl -fL -o "$tmp/inter.zip" "https://github.com/rsms/inter/releases/download/v$INTER_VERSION/Inter-$INTER_VERSION.zip"
+unzip -j -o "$tmp/inter.zip" "web/InterVariable.woff2" "LICENSE.txt" -d "$asset/inter"
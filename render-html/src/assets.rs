@@ -0,0 +1,169 @@
+use base64::{engine::general_purpose::STANDARD, Engine};
+use clap::ValueEnum;
+use serde::Deserialize;
+
+include!(concat!(env!("OUT_DIR"), "/bundled_assets.rs"));
+
+/// KaTeX 0.16.4, the version the `katex` crate typesets static math with. The stylesheet,
+/// fonts and CDN links are pinned to the same version, so every mode lays math out alike.
+const KATEX_JS: &str = include_str!("./asset/katex/katex.min.js");
+
+const KATEX_CSS: &str = "katex/katex.min.css";
+const LUCIDE_FONT: &str = "lucide/Lucide.ttf";
+const INTER_FONT: &str = "inter/InterVariable.woff2";
+
+/// Where the KaTeX, icon font and text font that the template needs come from
+#[derive(ValueEnum, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum AssetMode {
+    /// Inline everything into the document, so it renders without network access
+    SelfContained,
+    /// Link to jsdelivr, unpkg and Google Fonts
+    Cdn,
+}
+
+impl AssetMode {
+    /// Self-contained when this build bundles every asset it needs, which it does once
+    /// `fetch-assets.sh` has been run and its output committed, and CDNs otherwise
+    pub fn preferred() -> Self {
+        if [KATEX_CSS, LUCIDE_FONT, INTER_FONT]
+            .iter()
+            .all(|asset| bundled(asset).is_some())
+        {
+            AssetMode::SelfContained
+        } else {
+            AssetMode::Cdn
+        }
+    }
+}
+
+/// The `<head>` markup that loads KaTeX, the Lucide icon font and Inter, along with
+/// the names of any assets this build couldn't inline. KaTeX's script is only needed
+/// when math is typeset in the browser.
+pub fn head_assets(mode: AssetMode, katex_script: bool) -> (String, Vec<&'static str>) {
+    match mode {
+        AssetMode::Cdn => {
+            let mut html = CDN_KATEX_CSS.to_string();
+            if katex_script {
+                html += CDN_KATEX_JS;
+            }
+            html += CDN_FONTS;
+            (html, Vec::new())
+        }
+        AssetMode::SelfContained => self_contained_assets(katex_script),
+    }
+}
+
+/// Whether KaTeX's stylesheet will be in the document, which its HTML output needs
+pub fn has_katex_stylesheet(mode: AssetMode) -> bool {
+    mode == AssetMode::Cdn || bundled(KATEX_CSS).is_some()
+}
+
+fn self_contained_assets(katex_script: bool) -> (String, Vec<&'static str>) {
+    let mut html = String::new();
+    let mut missing = Vec::new();
+
+    html += "<!-- KaTeX (for math)! -->\n";
+    match bundled(KATEX_CSS) {
+        Some(css) => {
+            let css = inline_font_urls(&String::from_utf8_lossy(css), "katex/");
+            html += &format!("<style>{css}</style>\n");
+        }
+        None => {
+            // Without KaTeX's stylesheet and fonts, let the browser lay out MathML itself
+            missing.push("KaTeX stylesheet");
+            html += r#"<script>window.katexOptions = { output: "mathml" };</script>"#;
+            html += "\n";
+        }
+    }
+    if katex_script {
+        html += &format!("<script>{KATEX_JS}</script>\n");
+    }
+
+    html += "<!-- Lucide (for icons)! -->\n";
+    match bundled(LUCIDE_FONT) {
+        Some(font) => html += &font_face("LucideIcons", None, "font/ttf", "truetype", font),
+        None => missing.push("Lucide icon font"),
+    }
+
+    match bundled(INTER_FONT) {
+        Some(font) => html += &font_face("Inter", Some("100 900"), "font/woff2", "woff2", font),
+        None => missing.push("Inter font"),
+    }
+
+    (html, missing)
+}
+
+fn bundled(name: &str) -> Option<&'static [u8]> {
+    BUNDLED_ASSETS
+        .iter()
+        .find(|(asset, _)| *asset == name)
+        .map(|(_, bytes)| *bytes)
+}
+
+fn data_uri(mime: &str, bytes: &[u8]) -> String {
+    format!("data:{mime};base64,{}", STANDARD.encode(bytes))
+}
+
+fn font_face(family: &str, weight: Option<&str>, mime: &str, format: &str, bytes: &[u8]) -> String {
+    let weight = weight
+        .map(|w| format!("font-weight: {w};"))
+        .unwrap_or_default();
+    let src = data_uri(mime, bytes);
+
+    format!(
+        "<style>@font-face {{ font-family: '{family}'; {weight} font-display: block; src: url({src}) format('{format}'); }}</style>\n"
+    )
+}
+
+/// Swap the `url(fonts/...)` references in a bundled stylesheet for data URIs of the
+/// fonts bundled alongside it. Fonts that weren't bundled are left as they are.
+fn inline_font_urls(css: &str, folder: &str) -> String {
+    let mut result = String::with_capacity(css.len());
+    let mut rest = css;
+
+    while let Some(start) = rest.find("url(") {
+        let Some(end) = rest[start..].find(')').map(|i| i + start) else {
+            break;
+        };
+
+        let url = rest[start + 4..end].trim_matches(|c| c == '"' || c == '\'');
+        result += &rest[..start];
+
+        let mime = match url.rsplit('.').next() {
+            Some("woff2") => "font/woff2",
+            Some("woff") => "font/woff",
+            _ => "font/ttf",
+        };
+
+        match bundled(&format!("{folder}{url}")) {
+            Some(font) => result += &format!("url({})", data_uri(mime, font)),
+            None => result += &rest[start..=end],
+        }
+
+        rest = &rest[end + 1..];
+    }
+
+    result += rest;
+    result
+}
+
+const CDN_KATEX_CSS: &str = r#"<!-- KaTeX (for math)! -->
+        <link rel="stylesheet" href="https://cdn.jsdelivr.net/npm/katex@0.16.4/dist/katex.min.css" integrity="sha384-vKruj+a13U8yHIkAyGgK1J3ArTLzrFGBbBc0tDp4ad/EyewESeXE/Iv67Aj8gKZ0" crossorigin="anonymous">
+"#;
+
+const CDN_KATEX_JS: &str = r#"        <script src="https://cdn.jsdelivr.net/npm/katex@0.16.4/dist/katex.min.js" integrity="sha384-PwRUT/YqbnEjkZO0zZxNqcxACrXe+j766U2amXcgMg5457rve2Y7I6ZJSm2A0mS4" crossorigin="anonymous"></script>
+"#;
+
+const CDN_FONTS: &str = r#"
+        <!-- Lucide (for icons)! -->
+        <style>
+        @font-face {
+            font-family: 'LucideIcons';
+            src: url(https://unpkg.com/lucide-static@0.263.1/font/Lucide.ttf) format('truetype');
+        }
+        </style>
+
+        <link rel='preconnect' href='https://fonts.googleapis.com'>
+        <link rel='preconnect' href='https://fonts.gstatic.com' crossorigin>
+        <link href='https://fonts.googleapis.com/css2?family=Inter:wght@100;200;300;400;500;600;700;800;900&display=block' rel='stylesheet'>"#;
@@ -1,392 +1,605 @@
-use std::collections::VecDeque;
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    io::{self, Write},
+    path::{Path, PathBuf},
+    sync::OnceLock,
+};
 
 use markdown::mdast::{
-    BlockQuote, Break, Code, Delete, Emphasis, FootnoteDefinition, FootnoteReference, Heading,
-    Html, Image, ImageReference, InlineCode, InlineMath, Link, List, ListItem, Node, Paragraph,
-    Root, Strong, Table, TableCell, TableRow, Text, Toml, Yaml,
+    AlignKind, Definition, FootnoteReference, Heading, Node, Root, TableCell, TableRow, Text, Yaml,
 };
-use serde::de;
+use markdown::unist::Position;
+use regex::Regex;
 use serde_yaml::Value;
 
-macro_rules! simple_element {
-    ($inner:expr, $string:expr, $definitions:expr) => {{
-        for child in $inner {
-            ast_to_html_gather_definitions(child, $string, $definitions);
+use crate::{
+    callout::CalloutStyles,
+    cover::add_cover,
+    diagnostics::{Diagnostic, Severity},
+    embed::slice_note,
+    footnotes::{take_inline_footnotes, Footnotes},
+    handler::{NodeHandler, RenderContext},
+    headings::{heading_slug, toc_marker, HeadingIds, TocEntry, TocLevels},
+    highlight::prism_fallback_html,
+    image::{image_data_uri, is_remote_url, mime_type, percent_decode, split_image_size},
+    inline::strip_comments,
+    math::{render_tex, revert_loose_inline_math},
+    obsidian_vault::ObsidianVault,
+    options::RenderOptions,
+    wikilink::{split_wikilinks, TextSegment, Wikilink},
+};
+
+pub fn parse_markdown(input: &str) -> Result<Node, String> {
+    let mut ast = markdown::to_mdast(
+        input,
+        &markdown::ParseOptions {
+            constructs: markdown::Constructs {
+                frontmatter: true,
+                math_flow: true,
+                math_text: true,
+                ..markdown::Constructs::gfm()
+            },
+            gfm_strikethrough_single_tilde: false,
+            math_text_single_dollar: true,
+            mdx_expression_parse: None,
+            mdx_esm_parse: None,
+        },
+    )?;
+
+    revert_loose_inline_math(&mut ast, input);
+    Ok(ast)
+}
+
+/// Render a parsed note to HTML, writing it out a block at a time, and return any problems
+/// found on the way. The properties and footnotes are gathered up before anything is
+/// written, so the document comes out in order.
+pub fn write_html(
+    mut ast: Node,
+    vault: &ObsidianVault,
+    file: &Path,
+    options: &RenderOptions,
+    handler: &dyn NodeHandler,
+    out: &mut dyn Write,
+) -> io::Result<Vec<Diagnostic>> {
+    let mut definitions = Definitions::new(vault, file, options);
+
+    if options.strip_comments {
+        strip_comments(&mut ast);
+    }
+    take_inline_footnotes(&mut ast);
+    definitions.footnotes.add_definitions(file, &ast);
+    collect_link_definitions(&ast, &mut definitions.link_definitions);
+    collect_link_targets(&ast, &mut definitions);
+    collect_footnote_targets(&mut definitions);
+
+    let show_toc = !definitions.has_toc_marker && (options.toc || frontmatter_toc(&ast));
+    let mut cx = RenderContext::new(definitions, handler);
+    let mut block = String::new();
+
+    let frontmatter = frontmatter(&ast).map(|(yaml, _)| yaml);
+    add_cover(frontmatter.as_ref(), &mut block, &mut cx.definitions);
+
+    match ast.children().and_then(|c| c.first()) {
+        Some(Node::Yaml(yaml)) if options.show_properties => {
+            handler.yaml(yaml.clone(), &mut block, &mut cx)
         }
-    }};
-    ($inner:expr, $element:expr, $string:expr, $definitions:expr) => {{
-        *$string += "<";
-        *$string += $element;
-        *$string += ">";
-        simple_element!($inner, $string, $definitions);
-        *$string += "</";
-        *$string += $element;
-        *$string += ">";
-    }};
+        _ => {}
+    }
+
+    block += r#"<div class="non-meta-content">"#;
+
+    // Without a `[TOC]` marker, an asked-for table of contents goes after the properties
+    if show_toc {
+        handler.toc(TocLevels::default(), &mut block, &mut cx);
+    }
+    out.write_all(block.as_bytes())?;
+    block.clear();
+
+    let blocks = match ast {
+        Node::Root(Root { children, .. }) => children,
+        ast => vec![ast],
+    };
+
+    for child in blocks {
+        cx.render(child, &mut block);
+        out.write_all(block.as_bytes())?;
+        block.clear();
+    }
+
+    write_footnotes(&mut cx, out)?;
+    out.write_all(b"</div>")?;
+
+    if cx.definitions.needs_prism {
+        out.write_all(prism_fallback_html().as_bytes())?;
+    }
+
+    return Ok(cx.definitions.diagnostics);
 }
 
-const FN_PREFIX: &'static str = "fn-link-";
-const FN_REFERENCE_PREFIX: &'static str = "fn-ref-";
+/// Gather `[id]: url "title"` definitions so that reference-style links and images can be
+/// resolved wherever they appear. The first definition of an identifier wins.
+fn collect_link_definitions(ast: &Node, link_definitions: &mut HashMap<String, Definition>) {
+    if let Node::Definition(definition) = ast {
+        link_definitions
+            .entry(definition.identifier.clone())
+            .or_insert_with(|| definition.clone());
+    }
 
-pub fn ast_to_html(ast: Node) -> String {
-    let mut s = String::new();
-    let mut definitions = Definitions::new();
+    for child in ast.children().into_iter().flatten() {
+        collect_link_definitions(child, link_definitions);
+    }
+}
 
-    ast_to_html_gather_definitions(ast, &mut s, &mut definitions);
+/// Go through the footnotes `collect_link_targets` found references to, in the order
+/// they're numbered, including any referenced from other footnotes
+fn collect_footnote_targets(definitions: &mut Definitions) {
+    let mut number = 1;
+    while let Some((file, footnote)) = definitions.footnotes.get(number) {
+        definitions.files.push(file);
+        for child in &footnote.children {
+            collect_link_targets(child, definitions);
+        }
+        definitions.files.pop();
+        number += 1;
+    }
+}
 
-    s.insert_str(
-        0,
-        &format!(
-            r#"{}<div class="non-meta-content">"#,
-            definitions.yaml_meta_html()
-        ),
-    );
-    s += &definitions.footnote_html();
-    s += r#"</div>"#;
+/// The footnotes section, with every referenced footnote in the order it's numbered. Left
+/// out entirely if nothing is referenced.
+fn write_footnotes(cx: &mut RenderContext, out: &mut dyn Write) -> io::Result<()> {
+    if cx.definitions.footnotes.is_empty() {
+        return Ok(());
+    }
+
+    out.write_all(br#"<section class="footnotes"><hr><ol>"#)?;
+
+    let handler = cx.handler();
+    let mut footnote = String::new();
+    for number in 1..=cx.definitions.footnotes.len() {
+        let Some((file, definition)) = cx.definitions.footnotes.get(number) else {
+            continue;
+        };
+
+        cx.definitions.files.push(file);
+        handler.footnote_definition(definition, &mut footnote, cx);
+        cx.definitions.files.pop();
+
+        out.write_all(footnote.as_bytes())?;
+        footnote.clear();
+    }
 
-    return s;
+    out.write_all(b"</ol></section>")
 }
 
-pub fn ast_to_html_gather_definitions(
-    ast: Node,
-    string: &mut String,
-    definitions: &mut Definitions,
-) {
-    match ast {
-        Node::Yaml(Yaml { value, .. }) => {
-            add_pretty_yaml(value, &mut definitions.yaml_meta);
-        }
-        Node::Root(Root { children, .. }) => {
-            simple_element!(children, string, definitions);
-        }
-        Node::BlockQuote(BlockQuote { mut children, .. }) => {
-            if let Some((callout_type, callout_title)) =
-                find_callout_in_children_and_remove(Some(&mut children))
-            {
-                *string += &format!(r#"<div class="callout" data-callout="{callout_type}">"#);
+/// Whether the note's frontmatter asks for a table of contents with `toc: true`
+fn frontmatter_toc(ast: &Node) -> bool {
+    frontmatter(ast)
+        .and_then(|(yaml, _)| yaml.get("toc").and_then(Value::as_bool))
+        .unwrap_or(false)
+}
 
-                let title = callout_title.unwrap_or_else(|| capitalize(&callout_type));
+/// The note's frontmatter, if it has any that's valid YAML, and where it is
+pub(crate) fn frontmatter(ast: &Node) -> Option<(Value, Option<Position>)> {
+    let Some(Node::Yaml(Yaml { value, position })) = ast.children().and_then(|c| c.first()) else {
+        return None;
+    };
 
-                let icon_classname = find_callout_icon_classname(&callout_type);
+    let yaml = serde_yaml::from_str::<Value>(value).ok()?;
+    Some((yaml, position.clone()))
+}
 
-                *string += &format!(r#"<div class="callout-title">
-                    <div class="callout-icon"><i class="callout-icon-inner {icon_classname}"></i></div> 
-                    <div class="callout-title-inner">{title}</div>
-                </div>"#);
+/// Find every heading and whole-note embed that will end up in the document, following
+/// embeds into other notes, so that links to them can become in-document anchors. Headings
+/// get their unique ids here, in document order, for rendering to hand out again.
+///
+/// Footnotes are numbered as they're referenced, and gone through at the end, since
+/// that's where they're rendered.
+fn collect_link_targets(ast: &Node, definitions: &mut Definitions) {
+    if toc_marker(ast).is_some() {
+        definitions.has_toc_marker = true;
+    }
 
-                *string += r#"<div class="callout-content">"#;
-                simple_element!(children, string, definitions);
-                *string += r#"</div></div>"#;
-            } else {
-                simple_element!(children, "blockquote", string, definitions);
-            }
-        }
-        Node::FootnoteDefinition(FootnoteDefinition {
-            identifier,
-            children,
-            ..
-        }) => {
-            definitions.footnotes +=
-                &format!(r#"<li id="{FN_PREFIX}-{identifier}" value="{identifier}">"#);
-            simple_element!(
-                children,
-                &mut definitions.footnotes,
-                &mut Definitions::new()
-            );
-            definitions.footnotes +=
-                &format!(r##"<a href="#{FN_REFERENCE_PREFIX}-{identifier}">↩</a>"##);
-            definitions.footnotes += "</li>";
+    match ast {
+        Node::FootnoteDefinition(_) => {}
+        Node::FootnoteReference(FootnoteReference { identifier, .. }) => {
+            let file = definitions.current_file().to_path_buf();
+            definitions.footnotes.add_reference(&file, identifier);
         }
-        Node::List(List {
+        Node::Heading(Heading {
+            depth,
             children,
-            ordered,
-            start,
-            ..
+            position,
         }) => {
-            let tag_name = if ordered { "ol" } else { "ul" };
-            let start = start.unwrap_or(1);
-            *string += &format!(r#"<{tag_name} start="{start}">"#);
-            simple_element!(children, string, definitions);
-            *string += &format!(r#"</{tag_name}>"#);
-        }
-        Node::Break(_) => *string += "<br>",
-        Node::InlineCode(InlineCode { value, .. }) => {
-            *string += "<code>";
-            *string += &escape_html_str(value);
-            *string += "</code>";
+            let text = ast.to_string();
+            let slug = heading_slug(&text);
+            let id = definitions.heading_id_counter.unique(&slug);
+            let file = definitions.current_file().to_path_buf();
+
+            // Links to a repeated heading go to its first occurrence
+            definitions
+                .heading_anchors
+                .entry((file, slug))
+                .or_insert_with(|| id.clone());
+            if let Some(position) = position {
+                let key = definitions.heading_key(position);
+                definitions
+                    .heading_ids
+                    .entry(key)
+                    .or_default()
+                    .push_back(id.clone());
+            }
+            definitions.toc.push(TocEntry {
+                depth: *depth,
+                text,
+                id,
+            });
+
+            // Headings can hold footnote references and embeds too
+            for child in children {
+                collect_link_targets(child, definitions);
+            }
         }
-        Node::InlineMath(InlineMath { value, .. }) => {
-            *string += &format!(
-                "<span>{}</span><script>var target = document.currentScript.previousElementSibling;
+        Node::Text(Text { value, .. }) => {
+            for segment in split_wikilinks(value) {
+                let TextSegment::Wikilink(link) = segment else {
+                    continue;
+                };
+                if !link.embed {
+                    continue;
+                }
+                let Ok(Some((path, nodes))) = embedded_note(&link, definitions) else {
+                    continue;
+                };
 
-            katex.render(target.textContent, target, {{
-                throwOnError: false,
-                displayMode: false
-            }});</script>",
-                escape_html_str(value)
-            );
-        }
-        Node::Paragraph(Paragraph { children, .. }) => {
-            simple_element!(children, "p", string, definitions);
-        }
-        Node::Delete(Delete { children, .. }) => {
-            simple_element!(children, "del", string, definitions);
-        }
-        Node::Emphasis(Emphasis { children, .. }) => {
-            simple_element!(children, "em", string, definitions);
-        }
-        Node::FootnoteReference(FootnoteReference {
-            identifier, label, ..
-        }) => {
-            let label = label.as_ref().unwrap_or(&identifier);
-            *string += &format!(
-                r##"<sup><a id="{FN_REFERENCE_PREFIX}-{identifier}" href="#{FN_PREFIX}-{identifier}">[{label}]</a></sup>"##
-            );
+                if link.subpath.is_none() {
+                    definitions.embedded_notes.insert(path.clone());
+                }
+
+                definitions.files.push(path);
+                for node in &nodes {
+                    collect_link_targets(node, definitions);
+                }
+                definitions.files.pop();
+            }
         }
-        Node::Html(Html { value, .. }) => {
-            *string += &value;
+        _ => {
+            for child in ast.children().into_iter().flatten() {
+                collect_link_targets(child, definitions);
+            }
         }
-        Node::Image(Image {
-            alt, url, title, ..
-        }) => {
-            let title = title.unwrap_or_default();
+    }
+}
 
-            *string += &format!(r#"<img src="{url}" alt="{alt}" title="{title}"/>"#);
-        }
-        Node::Link(Link {
-            children,
-            title,
-            url,
-            ..
-        }) => {
-            let title = title.unwrap_or_default();
+/// `[text][label]`, `[text][]` and `![alt][label]` references left in text because nothing
+/// defines their label, as where each is in the text and a warning about it. The parser
+/// only makes reference nodes for labels that are defined, so these are the ones to warn
+/// about. Shortcut `[text]` references are left alone, since brackets in prose are common.
+pub(crate) fn unmatched_references(text: &str) -> Vec<(usize, usize, String)> {
+    static REFERENCE: OnceLock<Regex> = OnceLock::new();
+    let reference = REFERENCE.get_or_init(|| {
+        Regex::new(r"(!?)\[([^\[\]]*)\]\[([^\[\]]*)\]").expect("reference regex is valid")
+    });
+
+    reference
+        .captures_iter(text)
+        .filter_map(|captures| {
+            let whole = captures.get(0)?;
+            let label = match &captures[3] {
+                "" => &captures[2],
+                label => label,
+            };
+            if label.trim().is_empty() {
+                return None;
+            }
 
-            *string += &format!(r#"<a href="{url}" title="{title}">"#);
-            simple_element!(children, string, definitions);
-            *string += "</a>";
-        }
-        Node::Strong(Strong { children, .. }) => {
-            simple_element!(children, "strong", string, definitions);
-        }
-        Node::Text(Text { value, .. }) => {
-            *string += &escape_html_str(value);
-        }
-        Node::Code(Code { lang, value, .. }) => {
-            let classname = if let Some(lang) = lang {
-                format!("language-{lang}")
-            } else {
-                "".to_string()
+            let kind = match &captures[1] {
+                "!" => "image",
+                _ => "link",
             };
+            let message = format!("no definition for {kind} reference [{label}]");
+            Some((whole.start(), whole.end(), message))
+        })
+        .collect()
+}
 
-            *string += "<pre><code class=\"";
-            *string += &classname;
-            *string += "\">";
-            *string += &escape_html_str(value);
-            *string += "</code></pre>";
+pub(crate) fn wikilink_html(
+    link: &Wikilink,
+    position: Option<Position>,
+    definitions: &mut Definitions,
+) -> String {
+    let text = escape_html_str(&link.display_text());
+
+    let Some(path) = definitions
+        .vault
+        .resolve_link(link.target, definitions.current_file())
+    else {
+        definitions.warn(position, format!("unresolved link {}", link.source));
+        return format!(r#"<span class="internal-link is-unresolved">{text}</span>"#);
+    };
+
+    let is_root = path == definitions.files[0];
+    let in_document = is_root || definitions.embedded_notes.contains(&path);
+
+    let anchor = match link.subpath {
+        None if is_root => "#".to_string(),
+        None if in_document => format!("#{}", note_anchor(&path)),
+        None => return format!(r#"<span class="internal-link">{text}</span>"#),
+        Some(subpath) => {
+            let heading = subpath.rsplit('#').next().unwrap_or(subpath);
+            let slug = heading_slug(heading);
+
+            if let Some(id) = definitions.heading_anchors.get(&(path, slug)) {
+                format!("#{id}")
+            } else if in_document && !heading.starts_with('^') {
+                definitions.warn(
+                    position,
+                    format!("no heading \"{heading}\" for link {}", link.source),
+                );
+                return format!(r#"<span class="internal-link is-unresolved">{text}</span>"#);
+            } else {
+                return format!(r#"<span class="internal-link">{text}</span>"#);
+            }
         }
-        Node::Math(markdown::mdast::Math { value, .. }) => {
-            *string += &format!(
-                "<div>{}</div><script>var target = document.currentScript.previousElementSibling;
+    };
 
-            katex.render(target.textContent, target, {{
-                throwOnError: false,
-                displayMode: true
-            }});</script>",
-                escape_html_str(value)
-            );
-        }
-        Node::Heading(Heading {
-            children, depth, ..
-        }) => {
-            let heading_element_type = format!("h{depth}");
-            simple_element!(children, &heading_element_type, string, definitions);
-        }
-        Node::ThematicBreak(_) => {
-            *string += "<hr>";
-        }
-        Node::Table(Table { children, .. }) => {
-            simple_element!(children, "table", string, definitions);
-        }
-        Node::TableRow(TableRow { children, .. }) => {
-            simple_element!(children, "tr", string, definitions);
-        }
-        Node::TableCell(TableCell { children, .. }) => {
-            simple_element!(children, "td", string, definitions);
-        }
-        Node::ListItem(ListItem {
-            checked, children, ..
-        }) => {
-            *string += "<li>";
-            *string += match checked {
-                Some(true) => r#"<input type="checkbox" checked>"#,
-                Some(false) => r#"<input type="checkbox">"#,
-                None => "",
-            };
-            simple_element!(children, string, definitions);
-            *string += "</li>";
-        }
-        Node::Toml(Toml { value, .. }) => {
-            eprintln!("TOML frontmatter is not supported in gh-canvas");
+    format!(
+        r#"<a class="internal-link" href="{}">{text}</a>"#,
+        escape_html_str(&anchor)
+    )
+}
 
-            *string += r#"<pre><code class="language-toml">"#;
-            *string += &escape_html_str(value);
-            *string += "</code></pre>"
-        }
-        Node::Definition(_) | Node::ImageReference(_) | Node::LinkReference(_) => {
-            panic!("References and definitions are only supported for footnotes in gh-canvas");
-        }
-        Node::MdxJsxTextElement(_)
-        | Node::MdxTextExpression(_)
-        | Node::MdxFlowExpression(_)
-        | Node::MdxJsxFlowElement(_)
-        | Node::MdxjsEsm(_) => panic!("MDX is not supported by gh-canvas"),
+fn note_anchor(path: &Path) -> String {
+    let name = path.file_stem().unwrap_or_default().to_string_lossy();
+    format!("embed-{}", heading_slug(&name))
+}
+
+/// A table row, with each cell aligned the way its column's delimiter row asks
+pub(crate) fn table_row_html(
+    row: Node,
+    cell_element: &str,
+    align: &[AlignKind],
+    string: &mut String,
+    cx: &mut RenderContext,
+) {
+    let Node::TableRow(TableRow { children, .. }) = row else {
+        return cx.render(row, string);
+    };
+
+    *string += "<tr>";
+    for (cell, align) in children
+        .into_iter()
+        .zip(align.iter().chain(std::iter::repeat(&AlignKind::None)))
+    {
+        let scope = if cell_element == "th" {
+            r#" scope="col""#
+        } else {
+            ""
+        };
+        let style = match align {
+            AlignKind::Left => r#" style="text-align: left""#,
+            AlignKind::Right => r#" style="text-align: right""#,
+            AlignKind::Center => r#" style="text-align: center""#,
+            AlignKind::None => "",
+        };
+
+        *string += &format!("<{cell_element}{scope}{style}>");
+        match cell {
+            Node::TableCell(TableCell { children, .. }) => cx.render_children(children, string),
+            cell => cx.render(cell, string),
+        }
+        *string += &format!("</{cell_element}>");
     }
+    *string += "</tr>";
 }
 
-fn find_callout_icon_classname(callout_type: &String) -> &'static str {
-    match callout_type.as_str() {
-        "abstract" | "summary" | "tldr" => "icon-clipboard-list",
-        "info" => "icon-info",
-        "todo" => "icon-check-circle-2",
-        "important" => "icon-flame",
-        "tip" | "hint" => "icon-flame",
-        "success" | "check" | "done" => "icon-check",
-        "question" | "help" | "faq" => "icon-help-circle",
-        "warning" | "caution" | "attention" => "icon-alert-triangle",
-        "failure" | "fail" | "missing" => "icon-x",
-        "danger" | "error" => "icon-zap",
-        "bug" => "icon-bug",
-        "example" => "icon-list",
-        "quote" | "cite" => "icon-quote",
-        _ => "lucide-pencil",
+/// Whether a paragraph is nothing but a `![[note]]` embed, which renders as a block
+pub(crate) fn is_lone_note_embed(children: &[Node]) -> bool {
+    let [Node::Text(Text { value, .. })] = children else {
+        return false;
+    };
+
+    match split_wikilinks(value.trim()).as_slice() {
+        [TextSegment::Wikilink(link)] => link.embed && link.is_note(),
+        _ => false,
     }
 }
 
-fn capitalize(s: &String) -> String {
-    if s.is_empty() {return String::new();}
+pub(crate) fn embed_html(
+    link: &Wikilink,
+    position: Option<Position>,
+    string: &mut String,
+    cx: &mut RenderContext,
+) {
+    let definitions = &mut cx.definitions;
 
-    let first_letter = s.chars().next().unwrap().to_ascii_uppercase();
-    let rest = s[1..].to_ascii_lowercase();
+    if !link.is_note() {
+        *string += &attachment_embed_html(link, position, definitions);
+        return;
+    }
 
-    let mut result = String::new();
-    result.push(first_letter);
-    result.extend(rest.chars());
+    match embedded_note(link, definitions) {
+        Ok(Some((path, nodes))) => {
+            let id = match link.subpath {
+                None => format!(r#" id="{}""#, escape_html_str(&note_anchor(&path))),
+                Some(_) => String::new(),
+            };
 
-    return result;
-    
+            *string += &format!(r#"<div class="internal-embed markdown-embed"{id}>"#);
+            definitions.files.push(path);
+            cx.render_children(nodes, string);
+            cx.definitions.files.pop();
+            *string += "</div>";
+        }
+        Ok(None) => *string += &escape_html_str(link.source),
+        Err(message) => {
+            definitions.warn(position, message);
+            *string += &format!(
+                r#"<span class="internal-embed is-unresolved">{}</span>"#,
+                escape_html_str(link.source)
+            );
+        }
+    }
 }
 
-fn find_callout_in_children_and_remove<'a>(
-    ast: Option<&'a mut Vec<Node>>,
-) -> Option<(String, Option<String>)> {
-    let ast = ast?;
+fn attachment_embed_html(
+    link: &Wikilink,
+    position: Option<Position>,
+    definitions: &mut Definitions,
+) -> String {
+    let unresolved = format!(
+        r#"<span class="internal-embed is-unresolved">{}</span>"#,
+        escape_html_str(link.source)
+    );
 
-    if ast.is_empty() {
-        return None;
+    let Some(path) = definitions
+        .vault
+        .resolve_attachment(link.target, definitions.current_file())
+    else {
+        definitions.warn(position, format!("unresolved embed {}", link.source));
+        return unresolved;
+    };
+
+    if mime_type(&path).is_none() {
+        // PDFs, audio and the like can't be shown on a printed page, so just name them
+        return format!(
+            r#"<span class="internal-embed file-embed">{}</span>"#,
+            escape_html_str(&link.display_text())
+        );
     }
 
-    let first_elem = std::mem::replace(&mut ast[0], Node::Break(Break { position: None }));
+    let (alt, size) = split_image_size(link.alias.unwrap_or_default());
+    let alt = if alt.is_empty() { link.target } else { alt };
+    let size = size.map(|s| s.attributes()).unwrap_or_default();
 
-    match first_elem {
-        Node::Text(Text {
-            mut value,
-            position,
-        }) => {
-            if regex::Regex::new(r#"^\[![a-zA-Z_-]+\][^\n]*\n"#)
-                .unwrap()
-                .is_match(&value)
-            {
-                let nl_index = value.chars().position(|x| x == '\n').unwrap();
-                let name = value[2..nl_index].to_string();
-                value.drain(nl_index..);
-
-                let (name, title) = name.split_at(name.chars().position(|x| x == ']').unwrap());
-                let title = &title[1..];
-
-                let title = if title.is_empty() {
-                    None
-                } else {
-                    Some(title.to_string())
-                };
+    match image_data_uri(&path) {
+        Ok(src) => format!(
+            r#"<img class="internal-embed image-embed" src="{src}" alt="{}"{size}/>"#,
+            escape_html_str(alt)
+        ),
+        Err(e) => {
+            let message = format!("couldn't read {}: {e}", path.to_string_lossy());
+            definitions.warn(position, message);
+            unresolved
+        }
+    }
+}
 
-                return Some((name.to_string(), title));
-            } else {
-                ast[0] = Node::Text(Text { value, position });
-                return None;
+/// Inline a local image as a data URI. Remote images, images that can't be found in the
+/// vault and files that aren't images are left pointing at their original URL.
+pub(crate) fn local_image_src(
+    url: &str,
+    position: Option<Position>,
+    definitions: &mut Definitions,
+) -> String {
+    if is_remote_url(url) {
+        return url.to_string();
+    }
+
+    let target = percent_decode(url);
+
+    match definitions
+        .vault
+        .resolve_attachment(&target, definitions.current_file())
+    {
+        Some(path) if mime_type(&path).is_none() => {
+            let message = format!("not inlining {url}, which isn't a kind of image");
+            definitions.warn(position, message);
+            url.to_string()
+        }
+        Some(path) => match image_data_uri(&path) {
+            Ok(src) => src,
+            Err(e) => {
+                let message = format!("couldn't read {}: {e}", path.to_string_lossy());
+                definitions.warn(position, message);
+                url.to_string()
             }
-        }
-        node => {
-            ast[0] = node;
-            return find_callout_in_children_and_remove(ast[0].children_mut());
+        },
+        None => {
+            definitions.warn(position, format!("couldn't find image {url}"));
+            url.to_string()
         }
     }
 }
 
-fn add_pretty_yaml(value: String, string: &mut String) {
-    if let Ok(yaml) = serde_yaml::from_str::<serde_yaml::Value>(&value) {
-        *string += r#"<div class="properties">"#;
-        add_pretty_yaml_parsed(yaml, string);
-        *string += r#"</div>"#
-    } else {
-        *string += "<pre>";
-        *string += &escape_html_str(value);
-        *string += "</pre>";
+/// Load and slice the note a `![[note#subpath]]` embed points to. Gives `None` for embeds
+/// of files that aren't Markdown notes.
+fn embedded_note(
+    link: &Wikilink,
+    definitions: &mut Definitions,
+) -> Result<Option<(PathBuf, Vec<Node>)>, String> {
+    let Some(path) = definitions
+        .vault
+        .resolve_link(link.target, definitions.current_file())
+    else {
+        return Err(format!("unresolved embed {}", link.source));
+    };
+
+    if path.extension().is_none_or(|ext| ext != "md") {
+        return Ok(None);
+    }
+
+    if definitions.files.contains(&path) {
+        return Err(format!("embed {} includes itself", link.source));
+    }
+
+    if !definitions.notes.contains_key(&path) {
+        let markdown = std::fs::read_to_string(&path)
+            .map_err(|e| format!("couldn't read {}: {e}", path.to_string_lossy()))?;
+        let mut note = parse_markdown(&markdown)?;
+        if definitions.options.strip_comments {
+            strip_comments(&mut note);
+        }
+        take_inline_footnotes(&mut note);
+        definitions.footnotes.add_definitions(&path, &note);
+        collect_link_definitions(&note, &mut definitions.link_definitions);
+        definitions.notes.insert(path.clone(), note);
     }
+
+    let nodes = slice_note(&definitions.notes[&path], link.subpath).ok_or_else(|| {
+        format!(
+            "no section \"{}\" in {} for embed {}",
+            link.subpath.unwrap_or_default(),
+            path.file_stem().unwrap_or_default().to_string_lossy(),
+            link.source
+        )
+    })?;
+
+    return Ok(Some((path, nodes)));
 }
 
-fn add_pretty_yaml_parsed(value: Value, string: &mut String) {
-    match value {
-        Value::Null => {}
-        Value::Bool(b) => {
-            *string += if b {
-                r#"<input type="checkbox" checked>"#
-            } else {
-                r#"<input type="checkbox">"#
-            };
-        }
-        Value::Number(n) => {
-            *string += &format!("<code>{n}</code>");
-        }
-        Value::String(s) => {
-            *string += &escape_html_str(s);
-        }
-        Value::Sequence(list) => {
-            *string += "<ul>";
-            for itm in list {
-                *string += "<li>";
-                add_pretty_yaml_parsed(itm, string);
-                *string += "</li>";
-            }
-            *string += "</ul>";
-        }
-        Value::Mapping(map) => {
-            *string += "<dl>";
-            for (k, v) in map {
-                *string += "<dt>";
-                add_pretty_yaml_parsed(k, string);
-                *string += "</dt><dd>";
-                add_pretty_yaml_parsed(v, string);
-                *string += "</dd>";
-            }
-            *string += "</dl>";
-        }
-        Value::Tagged(tv) => {
-            *string += "<strong>";
-            *string += &tv.tag.to_string();
-            *string += "</strong>";
+pub(crate) fn static_math_html(
+    tex: &str,
+    display: bool,
+    position: Option<Position>,
+    string: &mut String,
+    definitions: &mut Definitions,
+) {
+    let (element, classname) = if display {
+        ("div", "math math-block")
+    } else {
+        ("span", "math math-inline")
+    };
 
-            add_pretty_yaml_parsed(tv.value, string);
+    match render_tex(tex, display, definitions.options.math_output()) {
+        Ok(html) => *string += &format!(r#"<{element} class="{classname}">{html}</{element}>"#),
+        Err(message) => {
+            definitions.warn(position, message);
+            *string += &format!(
+                r#"<{element} class="{classname} math-error"><code>{}</code></{element}>"#,
+                escape_html_str(tex)
+            );
         }
     }
 }
 
-fn escape_html_str(input: String) -> String {
+pub fn escape_html_str(input: &str) -> String {
     let mut r = String::new();
     for c in input.chars() {
         match c {
             '<' => r += "&lt;",
             '>' => r += "&gt;",
             '"' => r += "&quot;",
+            '\'' => r += "&#39;",
             '&' => r += "&amp;",
             _ => r.push(c),
         }
@@ -394,23 +607,86 @@ fn escape_html_str(input: String) -> String {
     return r;
 }
 
-pub struct Definitions {
-    footnotes: String,
-    yaml_meta: String,
+pub struct Definitions<'a> {
+    pub(crate) vault: &'a ObsidianVault,
+    pub(crate) options: &'a RenderOptions,
+    /// The note being rendered, followed by any notes it embeds that are being rendered
+    /// inside it
+    pub(crate) files: Vec<PathBuf>,
+    /// The id of the first heading with each slug in each note, for links to point at
+    pub(crate) heading_anchors: HashMap<(PathBuf, String), String>,
+    /// Unique heading ids by note and source offset, so a handler that renders some
+    /// headings itself doesn't shift the ids of the rest. A note embedded more than once
+    /// has an id per embed, taken off the front as its headings render.
+    pub(crate) heading_ids: HashMap<(PathBuf, usize), VecDeque<String>>,
+    pub(crate) heading_id_counter: HeadingIds,
+    pub(crate) toc: Vec<TocEntry>,
+    pub(crate) has_toc_marker: bool,
+    /// Whether a code block was left for Prism, because static highlighting didn't know
+    /// its language
+    pub(crate) needs_prism: bool,
+    pub(crate) embedded_notes: HashSet<PathBuf>,
+    pub(crate) notes: HashMap<PathBuf, Node>,
+    pub(crate) link_definitions: HashMap<String, Definition>,
+    pub(crate) footnotes: Footnotes,
+    pub(crate) callout_styles: CalloutStyles,
+    pub(crate) diagnostics: Vec<Diagnostic>,
 }
 
-impl Definitions {
-    pub fn new() -> Self {
+impl<'a> Definitions<'a> {
+    pub(crate) fn new(vault: &'a ObsidianVault, file: &Path, options: &'a RenderOptions) -> Self {
         Definitions {
-            footnotes: String::new(),
-            yaml_meta: String::new(),
+            vault,
+            options,
+            files: vec![file.to_path_buf()],
+            heading_anchors: HashMap::new(),
+            heading_ids: HashMap::new(),
+            heading_id_counter: HeadingIds::default(),
+            toc: Vec::new(),
+            has_toc_marker: false,
+            needs_prism: false,
+            embedded_notes: HashSet::new(),
+            notes: HashMap::new(),
+            link_definitions: HashMap::new(),
+            footnotes: Footnotes::default(),
+            callout_styles: CalloutStyles::load(vault),
+            diagnostics: Vec::new(),
         }
     }
-    pub fn yaml_meta_html(&self) -> &String {
-        &self.yaml_meta
+    pub fn warn(&mut self, position: Option<Position>, message: impl Into<String>) {
+        self.diagnose(Severity::Warning, position, message.into());
+    }
+    pub fn error(&mut self, position: Option<Position>, message: impl Into<String>) {
+        self.diagnose(Severity::Error, position, message.into());
+    }
+    fn diagnose(&mut self, severity: Severity, position: Option<Position>, message: String) {
+        let file = self.current_file().to_path_buf();
+        self.diagnostics.push(Diagnostic {
+            severity,
+            message,
+            file,
+            position,
+        });
+    }
+    fn heading_key(&self, position: &Position) -> (PathBuf, usize) {
+        (self.current_file().to_path_buf(), position.start.offset)
+    }
+    /// The unique id collected for the heading at `position` in the current note
+    pub(crate) fn take_heading_id(&mut self, position: &Position) -> Option<String> {
+        let key = self.heading_key(position);
+        self.heading_ids.get_mut(&key)?.pop_front()
+    }
+    pub fn vault(&self) -> &'a ObsidianVault {
+        self.vault
+    }
+    pub fn options(&self) -> &'a RenderOptions {
+        self.options
     }
-    pub fn footnote_html(&self) -> String {
-        let footnotes = &self.footnotes;
-        return format!(r#"<section class="footnotes"><hr><ol>{footnotes}</ol></section>"#);
+    /// The note whose nodes are being rendered, which is an embedded note while rendering
+    /// an embed
+    pub fn current_file(&self) -> &Path {
+        self.files
+            .last()
+            .expect("the rendered note is always in the stack")
     }
 }
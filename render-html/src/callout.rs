@@ -0,0 +1,291 @@
+use std::{collections::HashMap, sync::OnceLock};
+
+use markdown::mdast::{Node, Paragraph, Text};
+use regex::Regex;
+use serde_json::Value;
+
+use crate::obsidian_vault::ObsidianVault;
+
+/// The `[!type|metadata]+ Title` line that turns a blockquote into a callout
+pub struct Callout {
+    pub kind: String,
+    /// Anything after a `|` in the brackets, which themes use for layouts like `wide`
+    pub metadata: Option<String>,
+    /// `+` or `-` if the callout is foldable
+    pub fold: Option<char>,
+    /// Inline nodes making up a custom title, empty if there isn't one
+    pub title: Vec<Node>,
+}
+
+fn callout_regex() -> &'static Regex {
+    static CALLOUT: OnceLock<Regex> = OnceLock::new();
+    CALLOUT.get_or_init(|| {
+        Regex::new(r"^\[!([^\]|]+)(?:\|([^\]]*))?\]([+-])?\s*(.*)$")
+            .expect("callout regex is valid")
+    })
+}
+
+/// If a blockquote's children start with a callout line, take it out of them. The rest
+/// of the first line, formatting and all, becomes the title.
+pub fn take_callout(children: &mut Vec<Node>) -> Option<Callout> {
+    let Some(Node::Paragraph(Paragraph {
+        children: inline, ..
+    })) = children.first_mut()
+    else {
+        return None;
+    };
+    let Some(Node::Text(Text { value, .. })) = inline.first_mut() else {
+        return None;
+    };
+
+    let first_line = value.split('\n').next().unwrap_or_default();
+    let captures = callout_regex().captures(first_line)?;
+
+    let metadata = captures.get(2).map(|m| m.as_str().trim().to_string());
+    let mut callout = Callout {
+        kind: captures[1].trim().to_lowercase(),
+        metadata: metadata.filter(|m| !m.is_empty()),
+        fold: captures.get(3).and_then(|m| m.as_str().chars().next()),
+        title: Vec::new(),
+    };
+
+    let title = captures[4].to_string();
+    if !title.is_empty() {
+        callout.title.push(text_node(title));
+    }
+
+    if let Some((_, rest)) = value.split_once('\n') {
+        *value = rest.to_string();
+    } else {
+        inline.remove(0);
+
+        while !inline.is_empty() {
+            match &mut inline[0] {
+                Node::Break(_) => {
+                    inline.remove(0);
+                    break;
+                }
+                Node::Text(Text { value, .. }) if value.contains('\n') => {
+                    let (end_of_title, rest) = value.split_once('\n').unwrap_or_default();
+                    callout.title.push(text_node(end_of_title.to_string()));
+                    *value = rest.to_string();
+                    break;
+                }
+                _ => callout.title.push(inline.remove(0)),
+            }
+        }
+    }
+
+    if inline.iter().all(|node| node.to_string().trim().is_empty()) {
+        children.remove(0);
+    }
+
+    Some(callout)
+}
+
+fn text_node(value: String) -> Node {
+    Node::Text(Text {
+        value,
+        position: None,
+    })
+}
+
+/// How a type of callout looks, as far as something other than Obsidian's own stylesheet
+/// says. Unset fields fall through to the next source.
+#[derive(Default, Clone)]
+struct CalloutStyle {
+    icon: Option<String>,
+    /// An `r, g, b` triple, or anything else `rgba()` accepts as its colour
+    color: Option<String>,
+}
+
+impl CalloutStyle {
+    fn merge(&mut self, other: CalloutStyle) {
+        self.icon = other.icon.or(self.icon.take());
+        self.color = other.color.or(self.color.take());
+    }
+}
+
+/// Callout icons and colours defined by the vault's theme, its enabled CSS snippets and
+/// the Callout Manager plugin, in increasing order of precedence
+#[derive(Default)]
+pub struct CalloutStyles {
+    styles: HashMap<String, CalloutStyle>,
+    /// Colours from snippets and plugins, which don't make it into the document's CSS
+    /// and so have to be set on the callouts themselves
+    inline_colors: HashMap<String, String>,
+}
+
+impl CalloutStyles {
+    pub fn load(vault: &ObsidianVault) -> CalloutStyles {
+        let mut styles = CalloutStyles::default();
+
+        // The theme's stylesheet is in the document, so its colours already apply
+        if let Ok(Some(theme_css)) = vault.theme_css() {
+            for (kind, style) in css_callout_styles(&theme_css) {
+                styles.add(kind, style, false);
+            }
+        }
+        for css in vault.enabled_snippets_css() {
+            for (kind, style) in css_callout_styles(&css) {
+                styles.add(kind, style, true);
+            }
+        }
+        if let Some(data) = vault.plugin_data("callout-manager") {
+            for (kind, style) in callout_manager_styles(&data) {
+                styles.add(kind, style, true);
+            }
+        }
+
+        styles
+    }
+
+    fn add(&mut self, kind: String, style: CalloutStyle, inline_color: bool) {
+        if inline_color {
+            if let Some(color) = &style.color {
+                self.inline_colors.insert(kind.clone(), color.clone());
+            }
+        }
+        self.styles.entry(kind).or_default().merge(style);
+    }
+
+    /// The Lucide icon class for a callout type
+    pub fn icon_classname(&self, kind: &str) -> String {
+        match self
+            .styles
+            .get(kind)
+            .and_then(|style| style.icon.as_deref())
+        {
+            Some(icon) => lucide_classname(icon),
+            None => find_callout_icon_classname(kind).to_string(),
+        }
+    }
+
+    /// The colour to set on a callout of this type, if the document's CSS won't set it
+    pub fn inline_color(&self, kind: &str) -> Option<&str> {
+        self.inline_colors.get(kind).map(String::as_str)
+    }
+}
+
+/// `lucide-flame`, `flame` and `icon-flame` all mean the same icon
+fn lucide_classname(icon: &str) -> String {
+    let icon = icon.trim().trim_matches(|c| c == '"' || c == '\'');
+    let name = icon
+        .strip_prefix("lucide-")
+        .or_else(|| icon.strip_prefix("icon-"))
+        .unwrap_or(icon);
+
+    format!("icon-{name}")
+}
+
+/// Icons and colours set by `.callout[data-callout="type"] { ... }` rules
+fn css_callout_styles(css: &str) -> Vec<(String, CalloutStyle)> {
+    static RULE: OnceLock<Regex> = OnceLock::new();
+    static SELECTOR: OnceLock<Regex> = OnceLock::new();
+    static PROPERTY: OnceLock<Regex> = OnceLock::new();
+
+    let rule =
+        RULE.get_or_init(|| Regex::new(r"([^{}]*)\{([^{}]*)\}").expect("rule regex is valid"));
+    let selector = SELECTOR.get_or_init(|| {
+        Regex::new(r#"data-callout\s*=\s*["']?([^"'\]\s]+)"#).expect("selector regex is valid")
+    });
+    let property = PROPERTY.get_or_init(|| {
+        Regex::new(r"--callout-(icon|color)\s*:\s*([^;}]+)").expect("property regex is valid")
+    });
+
+    let mut styles = Vec::new();
+
+    for captures in rule.captures_iter(css) {
+        let mut style = CalloutStyle::default();
+        for property in property.captures_iter(&captures[2]) {
+            let value = property[2].trim().to_string();
+            match &property[1] {
+                "icon" => style.icon = Some(value),
+                _ => style.color = Some(value),
+            }
+        }
+
+        if style.icon.is_none() && style.color.is_none() {
+            continue;
+        }
+
+        for kind in selector.captures_iter(&captures[1]) {
+            styles.push((kind[1].to_lowercase(), style.clone()));
+        }
+    }
+
+    styles
+}
+
+/// Icons and colours from the Callout Manager plugin's settings, which look like
+/// `{ "callouts": { "settings": { "type": [{ "changes": { "icon": ..., "color": ... } }] } } }`.
+/// Changes that only apply under some condition, like a particular theme, are skipped.
+fn callout_manager_styles(data: &Value) -> Vec<(String, CalloutStyle)> {
+    let Some(settings) = data
+        .pointer("/callouts/settings")
+        .and_then(Value::as_object)
+    else {
+        return Vec::new();
+    };
+
+    let mut styles = Vec::new();
+
+    for (kind, changes) in settings {
+        for change in changes.as_array().into_iter().flatten() {
+            if change.get("condition").is_some_and(|c| !c.is_null()) {
+                continue;
+            }
+            let Some(changes) = change.get("changes") else {
+                continue;
+            };
+            let field = |name| {
+                changes
+                    .get(name)
+                    .and_then(Value::as_str)
+                    .map(str::to_string)
+            };
+
+            styles.push((
+                kind.to_lowercase(),
+                CalloutStyle {
+                    icon: field("icon"),
+                    color: field("color"),
+                },
+            ));
+        }
+    }
+
+    styles
+}
+
+fn find_callout_icon_classname(callout_type: &str) -> &'static str {
+    match callout_type {
+        "abstract" | "summary" | "tldr" => "icon-clipboard-list",
+        "info" => "icon-info",
+        "todo" => "icon-check-circle-2",
+        "important" => "icon-flame",
+        "tip" | "hint" => "icon-flame",
+        "success" | "check" | "done" => "icon-check",
+        "question" | "help" | "faq" => "icon-help-circle",
+        "warning" | "caution" | "attention" => "icon-alert-triangle",
+        "failure" | "fail" | "missing" => "icon-x",
+        "danger" | "error" => "icon-zap",
+        "bug" => "icon-bug",
+        "example" => "icon-list",
+        "quote" | "cite" => "icon-quote",
+        _ => "icon-pencil",
+    }
+}
+
+pub fn capitalize(s: &str) -> String {
+    let mut chars = s.chars();
+    let Some(first_letter) = chars.next() else {
+        return String::new();
+    };
+
+    let mut result = String::new();
+    result.extend(first_letter.to_uppercase());
+    result.push_str(&chars.as_str().to_lowercase());
+
+    return result;
+}
@@ -0,0 +1,218 @@
+use std::{path::Path, sync::OnceLock};
+
+use clap::ValueEnum;
+use regex::Regex;
+use serde::Deserialize;
+use serde_yaml::{Mapping, Value};
+
+use crate::{
+    ast_to_html::{escape_html_str, Definitions},
+    properties::{long_date, scalar_text},
+    sanitize::{sanitize_html, HtmlMode},
+};
+
+/// The built-in ways of laying out a cover made from the note's frontmatter
+#[derive(ValueEnum, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum CoverLayout {
+    /// The title, with who it's by and what it's for underneath, on a page of its own
+    Plain,
+    /// An APA-style title page
+    Apa,
+    /// An MLA heading at the top of the first page, rather than a page of its own
+    Mla,
+}
+
+/// What goes on a cover, each taken from the first of a few frontmatter keys that's set
+struct CoverFields {
+    title: String,
+    author: String,
+    institution: String,
+    course: String,
+    instructor: String,
+    /// The `date` property, as it was written
+    date: String,
+}
+
+impl CoverFields {
+    fn from_frontmatter(frontmatter: &Mapping, file: &Path) -> CoverFields {
+        let field = |keys: &[&str]| {
+            keys.iter()
+                .find_map(|key| frontmatter.get(*key))
+                .map(|value| match value {
+                    Value::Sequence(items) => {
+                        items.iter().map(scalar_text).collect::<Vec<_>>().join(", ")
+                    }
+                    value => scalar_text(value),
+                })
+                .unwrap_or_default()
+        };
+
+        let mut title = field(&["title"]);
+        if title.is_empty() {
+            title = file
+                .file_stem()
+                .unwrap_or_default()
+                .to_string_lossy()
+                .into();
+        }
+
+        CoverFields {
+            title,
+            author: field(&["author", "authors", "name", "student"]),
+            institution: field(&["institution", "school", "university"]),
+            course: field(&["course", "class"]),
+            instructor: field(&["instructor", "professor", "teacher"]),
+            date: field(&["date", "due"]),
+        }
+    }
+
+    /// The date in words, or as it was written if it isn't a date
+    fn date(&self, day_first: bool) -> String {
+        long_date(&self.date, day_first).unwrap_or_else(|| self.date.clone())
+    }
+
+    /// The value for a `{{placeholder}}` in a cover template. Anything that isn't one of
+    /// the cover's own fields is looked up in the frontmatter.
+    fn placeholder(&self, name: &str, frontmatter: &Mapping) -> Option<String> {
+        match name {
+            "title" => Some(self.title.clone()),
+            "author" => Some(self.author.clone()),
+            "institution" => Some(self.institution.clone()),
+            "course" => Some(self.course.clone()),
+            "instructor" => Some(self.instructor.clone()),
+            "date" => Some(self.date(false)),
+            name => frontmatter.get(name).map(scalar_text),
+        }
+    }
+}
+
+/// The cover the options ask for, made from the note's frontmatter. A template, if there
+/// is one, takes the place of the built-in layout.
+pub(crate) fn add_cover(
+    frontmatter: Option<&Value>,
+    string: &mut String,
+    definitions: &mut Definitions,
+) {
+    let options = definitions.options();
+    if options.cover.is_none() && options.cover_template.is_none() {
+        return;
+    }
+
+    let empty = Mapping::new();
+    let frontmatter = frontmatter.and_then(Value::as_mapping).unwrap_or(&empty);
+    let fields = CoverFields::from_frontmatter(frontmatter, &definitions.files[0]);
+
+    if let Some(template) = &options.cover_template {
+        match read_template(template, definitions) {
+            Ok(template) => {
+                *string += r#"<section class="cover-page cover-template">"#;
+                template_html(&template, &fields, frontmatter, string, definitions);
+                *string += "</section>";
+            }
+            Err(e) => definitions.error(None, e),
+        }
+        return;
+    }
+
+    let lines = |lines: &[&str], string: &mut String| {
+        for line in lines.iter().filter(|line| !line.is_empty()) {
+            *string += &format!("<p>{}</p>", escape_html_str(line));
+        }
+    };
+    let title = escape_html_str(&fields.title);
+
+    match options.cover {
+        Some(layout @ (CoverLayout::Plain | CoverLayout::Apa)) => {
+            let classname = match layout {
+                CoverLayout::Apa => "cover-apa",
+                _ => "cover-plain",
+            };
+            *string += &format!(r#"<section class="cover-page {classname}">"#);
+            *string += &format!(r#"<p class="cover-title">{title}</p>"#);
+            lines(
+                &[
+                    &fields.author,
+                    &fields.institution,
+                    &fields.course,
+                    &fields.instructor,
+                    &fields.date(false),
+                ],
+                string,
+            );
+            *string += "</section>";
+        }
+        Some(CoverLayout::Mla) => {
+            *string += r#"<header class="cover-mla">"#;
+            lines(
+                &[
+                    &fields.author,
+                    &fields.instructor,
+                    &fields.course,
+                    &fields.date(true),
+                ],
+                string,
+            );
+            *string += &format!(r#"<p class="cover-title">{title}</p>"#);
+            *string += "</header>";
+        }
+        None => {}
+    }
+}
+
+/// A cover template, with a relative path taken as relative to the vault
+fn read_template(template: &Path, definitions: &Definitions) -> Result<String, String> {
+    let path = definitions.vault().root().join(template);
+
+    std::fs::read_to_string(path)
+        .map_err(|e| format!("couldn't read cover template {}: {e}", template.display()))
+}
+
+/// A cover template with its `{{placeholders}}` filled in
+fn template_html(
+    template: &str,
+    fields: &CoverFields,
+    frontmatter: &Mapping,
+    string: &mut String,
+    definitions: &mut Definitions,
+) {
+    static PLACEHOLDER: OnceLock<Regex> = OnceLock::new();
+    let placeholder = PLACEHOLDER
+        .get_or_init(|| Regex::new(r"\{\{\s*([\w-]+)\s*\}\}").expect("placeholder regex is valid"));
+
+    let options = definitions.options();
+    let template = match options.html {
+        HtmlMode::Keep => template.to_string(),
+        HtmlMode::Sanitize => {
+            let (html, removed) = sanitize_html(template, &options.html_allow_list);
+            if !removed.is_empty() {
+                definitions.warn(
+                    None,
+                    format!("removed {} from the cover template", removed.join(", ")),
+                );
+            }
+            html
+        }
+    };
+
+    let mut unknown = Vec::new();
+    *string += &placeholder.replace_all(&template, |captures: &regex::Captures| {
+        match fields.placeholder(&captures[1], frontmatter) {
+            Some(value) => escape_html_str(&value),
+            None => {
+                unknown.push(captures[1].to_string());
+                String::new()
+            }
+        }
+    });
+
+    if !unknown.is_empty() {
+        definitions.warn(
+            None,
+            format!(
+                "nothing in the frontmatter for cover template placeholders {}",
+                unknown.join(", ")
+            ),
+        );
+    }
+}
@@ -0,0 +1,143 @@
+use std::{
+    fmt::{self, Display},
+    path::{Path, PathBuf},
+};
+
+use markdown::unist::Position;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+impl Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Severity::Error => write!(f, "error"),
+            Severity::Warning => write!(f, "warning"),
+        }
+    }
+}
+
+/// A problem found while rendering a note, pointing at where it is in the Markdown.
+/// Displays in the same format as compiler errors, with the offending line underlined.
+#[derive(Clone, Debug)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub message: String,
+    pub file: PathBuf,
+    pub position: Option<Position>,
+}
+
+/// A problem that stops a note from rendering at all, like the note or the vault being
+/// unreadable. Problems inside the Markdown are [`Diagnostic`]s instead.
+#[derive(Clone, Debug)]
+pub struct RenderError {
+    pub message: String,
+    pub file: PathBuf,
+}
+
+impl RenderError {
+    pub fn new(file: &Path, message: impl Into<String>) -> Self {
+        RenderError {
+            message: message.into(),
+            file: file.to_path_buf(),
+        }
+    }
+}
+
+impl std::error::Error for RenderError {}
+
+impl Display for RenderError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write_diagnostic(f, Severity::Error, &self.message, &self.file, None)
+    }
+}
+
+impl Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write_diagnostic(
+            f,
+            self.severity,
+            &self.message,
+            &self.file,
+            self.position.as_ref(),
+        )
+    }
+}
+
+fn write_diagnostic(
+    f: &mut fmt::Formatter<'_>,
+    severity: Severity,
+    message: &str,
+    file: &Path,
+    position: Option<&Position>,
+) -> fmt::Result {
+    let path = std::env::current_dir()
+        .ok()
+        .and_then(|cwd| file.strip_prefix(cwd).ok().map(Path::to_path_buf))
+        .unwrap_or_else(|| file.to_path_buf());
+    let path = path.to_string_lossy();
+
+    writeln!(f, "{severity}: {message}")?;
+
+    let Some(position) = position else {
+        return write!(f, " --> {path}");
+    };
+
+    let (line, column) = (position.start.line, position.start.column);
+    let gutter = " ".repeat(line.to_string().len());
+    write!(f, "{gutter}--> {path}:{line}:{column}")?;
+
+    let Some(source_line) = std::fs::read_to_string(file)
+        .ok()
+        .and_then(|source| source.lines().nth(line - 1).map(str::to_string))
+    else {
+        return Ok(());
+    };
+
+    let line_chars = source_line.chars().count();
+    let start = (column - 1).min(line_chars);
+    let end = if position.end.line == line {
+        (position.end.column - 1).min(line_chars)
+    } else {
+        line_chars
+    };
+
+    writeln!(f)?;
+    writeln!(f, "{gutter} |")?;
+    writeln!(f, "{line} | {source_line}")?;
+    write!(
+        f,
+        "{gutter} | {}{}",
+        " ".repeat(start),
+        "^".repeat(end.saturating_sub(start).max(1))
+    )
+}
+
+/// The position of `value[start..end]`, given the position of the whole text node. Only
+/// narrowed when the text is on one line, since block prefixes like `> ` on later lines
+/// would throw the columns off.
+pub fn sub_position(
+    position: Option<&Position>,
+    value: &str,
+    start: usize,
+    end: usize,
+) -> Option<Position> {
+    let mut position = position?.clone();
+
+    if value.contains('\n') || position.start.line != position.end.line {
+        return Some(position);
+    }
+
+    let column = position.start.column;
+    let offset = position.start.offset;
+
+    position.start.column = column + value[..start].chars().count();
+    position.start.offset = offset + start;
+    position.end.column = column + value[..end].chars().count();
+    position.end.offset = offset + end;
+
+    Some(position)
+}
@@ -0,0 +1,103 @@
+use markdown::mdast::{List, ListItem, Node, Paragraph, Text};
+
+use crate::headings::heading_slug;
+
+/// The part of a parsed note that a `![[note#subpath]]` embed refers to: the whole note,
+/// one heading's section, or a single `^block-id` block. Frontmatter is never embedded.
+pub fn slice_note(note: &Node, subpath: Option<&str>) -> Option<Vec<Node>> {
+    let children: Vec<&Node> = note
+        .children()?
+        .iter()
+        .filter(|node| !matches!(node, Node::Yaml(_) | Node::Toml(_)))
+        .collect();
+
+    let Some(subpath) = subpath else {
+        return Some(children.into_iter().cloned().collect());
+    };
+
+    let last = subpath.rsplit('#').next().unwrap_or(subpath);
+
+    if let Some(block_id) = last.strip_prefix('^') {
+        return find_block(&children, block_id).map(|block| vec![block]);
+    }
+
+    let slug = heading_slug(last);
+
+    let start = children.iter().position(|node| match node {
+        Node::Heading(_) => heading_slug(&node.to_string()) == slug,
+        _ => false,
+    })?;
+
+    let Node::Heading(heading) = children[start] else {
+        return None;
+    };
+
+    let end = children[start + 1..]
+        .iter()
+        .position(|node| matches!(node, Node::Heading(h) if h.depth <= heading.depth))
+        .map_or(children.len(), |i| i + start + 1);
+
+    return Some(children[start..end].iter().map(|&n| n.clone()).collect());
+}
+
+fn find_block(children: &[&Node], block_id: &str) -> Option<Node> {
+    for (i, node) in children.iter().enumerate() {
+        match node {
+            Node::Paragraph(paragraph) => match paragraph_block_id(paragraph) {
+                // An id on a line of its own names the block before it, e.g. a table
+                Some(("", id)) if id == block_id && i > 0 => return Some(children[i - 1].clone()),
+                Some((_, id)) if id == block_id => return Some((*node).clone()),
+                _ => {}
+            },
+            Node::ListItem(ListItem { children: item, .. }) => {
+                if let Some(Node::Paragraph(paragraph)) = item.first() {
+                    if paragraph_block_id(paragraph).is_some_and(|(_, id)| id == block_id) {
+                        return Some(Node::List(List {
+                            children: vec![(*node).clone()],
+                            position: None,
+                            ordered: false,
+                            start: None,
+                            spread: false,
+                        }));
+                    }
+                }
+            }
+            _ => {}
+        }
+
+        if let Some(grandchildren) = node.children() {
+            let grandchildren: Vec<&Node> = grandchildren.iter().collect();
+            if let Some(block) = find_block(&grandchildren, block_id) {
+                return Some(block);
+            }
+        }
+    }
+
+    None
+}
+
+fn paragraph_block_id(paragraph: &Paragraph) -> Option<(&str, &str)> {
+    match paragraph.children.last()? {
+        Node::Text(Text { value, .. }) => split_block_id(value),
+        _ => None,
+    }
+}
+
+/// Split a trailing Obsidian block id (`some text ^my-block`) off the end of a text run
+pub fn split_block_id(text: &str) -> Option<(&str, &str)> {
+    let trimmed = text.trim_end();
+    let caret = trimmed.rfind('^')?;
+
+    let before = &trimmed[..caret];
+    let id = &trimmed[caret + 1..];
+
+    if id.is_empty() || !id.chars().all(|c| c.is_ascii_alphanumeric() || c == '-') {
+        return None;
+    }
+
+    if !before.is_empty() && !before.ends_with(char::is_whitespace) {
+        return None;
+    }
+
+    Some((before.trim_end(), id))
+}
@@ -0,0 +1,287 @@
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+};
+
+use markdown::mdast::{FootnoteDefinition, FootnoteReference, Node, Paragraph, Text};
+
+use crate::diagnostics::sub_position;
+
+const FN_PREFIX: &str = "fn-link-";
+const FN_REFERENCE_PREFIX: &str = "fn-ref-";
+
+/// The id of a footnote's entry in the footnotes section
+pub fn footnote_id(number: usize) -> String {
+    format!("{FN_PREFIX}{number}")
+}
+
+/// The id of one of the references to a footnote. The first keeps the plain id, and later
+/// ones are suffixed `-2`, `-3`, ... so each gets its own back-link.
+pub fn reference_id(number: usize, reference: usize) -> String {
+    match reference {
+        0 | 1 => format!("{FN_REFERENCE_PREFIX}{number}"),
+        _ => format!("{FN_REFERENCE_PREFIX}{number}-{reference}"),
+    }
+}
+
+struct Footnote {
+    file: PathBuf,
+    identifier: String,
+    /// How many references to the footnote there are in the document
+    references: usize,
+    /// How many of those have been rendered so far
+    rendered: usize,
+}
+
+/// The footnotes of the note and the notes it embeds, numbered in the order they're first
+/// referenced. Identifiers only mean something within their own note, so footnotes are
+/// told apart by the note they're in as well.
+#[derive(Default)]
+pub struct Footnotes {
+    definitions: HashMap<(PathBuf, String), FootnoteDefinition>,
+    numbers: HashMap<(PathBuf, String), usize>,
+    referenced: Vec<Footnote>,
+}
+
+impl Footnotes {
+    /// Gather every footnote definition in a note, wherever it is, so that references in
+    /// any part of the note can find it. The first definition of an identifier wins.
+    pub fn add_definitions(&mut self, file: &Path, ast: &Node) {
+        if let Node::FootnoteDefinition(definition) = ast {
+            self.definitions
+                .entry((file.to_path_buf(), definition.identifier.clone()))
+                .or_insert_with(|| definition.clone());
+            return;
+        }
+
+        for child in ast.children().into_iter().flatten() {
+            self.add_definitions(file, child);
+        }
+    }
+
+    /// Count a reference to a footnote, numbering the footnote if this is the first.
+    /// Returns `None` if the note doesn't define it.
+    pub fn add_reference(&mut self, file: &Path, identifier: &str) -> Option<usize> {
+        let key = (file.to_path_buf(), identifier.to_string());
+        if !self.definitions.contains_key(&key) {
+            return None;
+        }
+
+        let number = *self.numbers.entry(key).or_insert_with(|| {
+            self.referenced.push(Footnote {
+                file: file.to_path_buf(),
+                identifier: identifier.to_string(),
+                references: 0,
+                rendered: 0,
+            });
+            self.referenced.len()
+        });
+        self.referenced[number - 1].references += 1;
+
+        Some(number)
+    }
+
+    /// How many footnotes are referenced
+    pub fn len(&self) -> usize {
+        self.referenced.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.referenced.is_empty()
+    }
+
+    /// The footnote with a number, and the note it's defined in
+    pub fn get(&self, number: usize) -> Option<(PathBuf, FootnoteDefinition)> {
+        let footnote = self.referenced.get(number.checked_sub(1)?)?;
+        let definition = &self.definitions[&(footnote.file.clone(), footnote.identifier.clone())];
+
+        Some((footnote.file.clone(), definition.clone()))
+    }
+
+    pub fn number(&self, file: &Path, identifier: &str) -> Option<usize> {
+        self.numbers
+            .get(&(file.to_path_buf(), identifier.to_string()))
+            .copied()
+    }
+
+    /// How many references a footnote has, and so how many back-links it needs
+    pub fn reference_count(&self, number: usize) -> usize {
+        number
+            .checked_sub(1)
+            .and_then(|i| self.referenced.get(i))
+            .map_or(0, |footnote| footnote.references)
+    }
+
+    /// Take the next reference to a footnote as it's rendered, giving its number and
+    /// which of its references this is, counting from 1
+    pub fn next_reference(&mut self, file: &Path, identifier: &str) -> Option<(usize, usize)> {
+        let number = self.number(file, identifier)?;
+        let footnote = &mut self.referenced[number - 1];
+        footnote.rendered += 1;
+
+        Some((number, footnote.rendered))
+    }
+}
+
+/// Turn Obsidian's inline footnotes, `^[text]`, into references to footnote definitions
+/// added to the end of the note
+pub fn take_inline_footnotes(ast: &mut Node) {
+    let Some(children) = ast.children_mut() else {
+        return;
+    };
+
+    let mut footnotes = Vec::new();
+    take_from_children(children, &mut footnotes);
+
+    for (i, content) in footnotes.into_iter().enumerate() {
+        children.push(Node::FootnoteDefinition(FootnoteDefinition {
+            children: vec![Node::Paragraph(Paragraph {
+                children: content,
+                position: None,
+            })],
+            position: None,
+            identifier: inline_identifier(i),
+            label: None,
+        }));
+    }
+}
+
+/// Identifiers from the parser are trimmed, so one with a leading space can't clash with
+/// a footnote written out in full
+fn inline_identifier(i: usize) -> String {
+    format!(" inline-{}", i + 1)
+}
+
+fn take_from_children(children: &mut Vec<Node>, footnotes: &mut Vec<Vec<Node>>) {
+    let mut i = 0;
+    let mut search_from = 0;
+
+    while i < children.len() {
+        let Node::Text(Text { value, .. }) = &children[i] else {
+            if let Some(grandchildren) = children[i].children_mut() {
+                take_from_children(grandchildren, footnotes);
+            }
+            i += 1;
+            search_from = 0;
+            continue;
+        };
+
+        let Some(start) = value[search_from..].find("^[").map(|s| s + search_from) else {
+            i += 1;
+            search_from = 0;
+            continue;
+        };
+
+        let Some((last, end)) = closing_bracket(children, i, start + 2) else {
+            search_from = start + 2;
+            continue;
+        };
+
+        let mut taken = children.drain(i..=last).collect::<Vec<_>>();
+        let (before, mut content, after) = if taken.len() == 1 {
+            let (value, position) = text_parts(&taken[0]);
+            (
+                text_node(&value[..start], sub_position(position, value, 0, start)),
+                vec![text_node(
+                    &value[start + 2..end],
+                    sub_position(position, value, start + 2, end),
+                )],
+                text_node(
+                    &value[end + 1..],
+                    sub_position(position, value, end + 1, value.len()),
+                ),
+            )
+        } else {
+            let last_text = taken.pop().expect("there are at least two nodes");
+            let first_text = taken.remove(0);
+            let (first, first_position) = text_parts(&first_text);
+            let (last, last_position) = text_parts(&last_text);
+
+            let mut content = vec![text_node(
+                &first[start + 2..],
+                sub_position(first_position, first, start + 2, first.len()),
+            )];
+            content.append(&mut taken);
+            content.push(text_node(
+                &last[..end],
+                sub_position(last_position, last, 0, end),
+            ));
+
+            (
+                text_node(
+                    &first[..start],
+                    sub_position(first_position, first, 0, start),
+                ),
+                content,
+                text_node(
+                    &last[end + 1..],
+                    sub_position(last_position, last, end + 1, last.len()),
+                ),
+            )
+        };
+        content.retain(|node| !matches!(node, Node::Text(Text { value, .. }) if value.is_empty()));
+
+        let mut replacement = Vec::new();
+        if !before.to_string().is_empty() {
+            replacement.push(before);
+        }
+        replacement.push(Node::FootnoteReference(FootnoteReference {
+            position: None,
+            identifier: inline_identifier(footnotes.len()),
+            label: None,
+        }));
+        let next = i + replacement.len();
+        if !after.to_string().is_empty() {
+            replacement.push(after);
+        }
+
+        footnotes.push(content);
+        children.splice(i..i, replacement);
+        i = next;
+        search_from = 0;
+    }
+}
+
+/// Where the `]` closing an inline footnote is: the index of the text node it's in, and
+/// its offset there. Brackets in between have to balance, so `^[see [[Note]]]` works.
+fn closing_bracket(children: &[Node], first: usize, from: usize) -> Option<(usize, usize)> {
+    let mut depth = 0;
+
+    for (i, child) in children.iter().enumerate().skip(first) {
+        let Node::Text(Text { value, .. }) = child else {
+            continue;
+        };
+        let from = if i == first { from } else { 0 };
+
+        for (offset, c) in value[from..].char_indices() {
+            match c {
+                '[' => depth += 1,
+                ']' if depth == 0 => {
+                    // `^[]` is just text
+                    if i == first && offset == 0 {
+                        return None;
+                    }
+                    return Some((i, from + offset));
+                }
+                ']' => depth -= 1,
+                _ => {}
+            }
+        }
+    }
+
+    None
+}
+
+fn text_parts(node: &Node) -> (&str, Option<&markdown::unist::Position>) {
+    match node {
+        Node::Text(Text { value, position }) => (value, position.as_ref()),
+        _ => ("", None),
+    }
+}
+
+fn text_node(value: &str, position: Option<markdown::unist::Position>) -> Node {
+    Node::Text(Text {
+        value: value.to_string(),
+        position,
+    })
+}
@@ -0,0 +1,585 @@
+use markdown::mdast::{
+    BlockQuote, Break, Code, Definition, Delete, Emphasis, FootnoteDefinition, FootnoteReference,
+    Heading, Html, Image, ImageReference, InlineCode, InlineMath, Link, LinkReference, List,
+    ListItem, Math, Node, Paragraph, Root, Strong, Table, TableCell, TableRow, Text, ThematicBreak,
+    Toml, Yaml,
+};
+
+use crate::{
+    ast_to_html::{
+        embed_html, escape_html_str, is_lone_note_embed, local_image_src, static_math_html,
+        table_row_html, unmatched_references, wikilink_html, Definitions,
+    },
+    callout::{capitalize, take_callout},
+    diagnostics::sub_position,
+    embed::split_block_id,
+    footnotes::{footnote_id, reference_id},
+    headings::{heading_slug, toc_html, toc_marker, TocLevels},
+    highlight::{highlight_code, HighlightMode, PRISM_FALLBACK_CLASS},
+    image::split_image_size,
+    inline::inline_html,
+    math::MathMode,
+    page::page_break_marker,
+    properties::add_pretty_yaml,
+    sanitize::{is_safe_url, sanitize_html, HtmlMode},
+    wikilink::{split_wikilinks, TextSegment},
+};
+
+/// Turns each kind of Markdown node into HTML. Every method has a default that gives the
+/// usual output, so a handler only needs to override the kinds it wants to change. An
+/// override can still get the usual output from [`DefaultHandler`], and children are
+/// rendered through [`RenderContext::render`], so they go through the same handler.
+pub trait NodeHandler {
+    fn root(&self, node: Root, out: &mut String, cx: &mut RenderContext) {
+        cx.render_children(node.children, out);
+    }
+
+    /// The frontmatter, which becomes the properties block at the top of the document
+    fn yaml(&self, node: Yaml, out: &mut String, cx: &mut RenderContext) {
+        add_pretty_yaml(node.value, node.position, out, &mut cx.definitions);
+    }
+
+    fn toml(&self, node: Toml, out: &mut String, cx: &mut RenderContext) {
+        cx.definitions.warn(
+            node.position,
+            "TOML frontmatter is not supported in gh-canvas",
+        );
+
+        *out += r#"<pre><code class="language-toml">"#;
+        *out += &escape_html_str(&node.value);
+        *out += "</code></pre>"
+    }
+
+    /// A table of contents, in place of a `[TOC]` paragraph or `toc` code block
+    fn toc(&self, levels: TocLevels, out: &mut String, cx: &mut RenderContext) {
+        *out += &toc_html(&cx.definitions.toc, &levels);
+    }
+
+    /// A new page, in place of a `<!-- pagebreak -->` comment or a `\newpage` line
+    fn page_break(&self, out: &mut String, _cx: &mut RenderContext) {
+        *out += r#"<div class="page-break"></div>"#;
+    }
+
+    fn block_quote(&self, node: BlockQuote, out: &mut String, cx: &mut RenderContext) {
+        let mut children = node.children;
+        let Some(callout) = take_callout(&mut children) else {
+            cx.render_element("blockquote", children, out);
+            return;
+        };
+
+        let kind = escape_html_str(&callout.kind);
+        let metadata = escape_html_str(callout.metadata.as_deref().unwrap_or_default());
+        // Foldable callouts print expanded, since there's no clicking them open on paper
+        let (classname, fold) = match callout.fold {
+            Some(fold) => (
+                "callout is-collapsible",
+                format!(r#" data-callout-fold="{fold}""#),
+            ),
+            None => ("callout", String::new()),
+        };
+        let style = match cx.definitions.callout_styles.inline_color(&callout.kind) {
+            Some(color) => format!(r#" style="--callout-color: {};""#, escape_html_str(color)),
+            None => String::new(),
+        };
+        *out += &format!(
+            r#"<div class="{classname}" data-callout="{kind}" data-callout-metadata="{metadata}"{fold}{style}>"#
+        );
+
+        let mut title = String::new();
+        if callout.title.is_empty() {
+            title = escape_html_str(&capitalize(&callout.kind));
+        } else {
+            cx.render_children(callout.title, &mut title);
+        }
+
+        let icon_classname =
+            escape_html_str(&cx.definitions.callout_styles.icon_classname(&callout.kind));
+
+        *out += &format!(
+            r#"<div class="callout-title">
+                    <div class="callout-icon"><i class="callout-icon-inner {icon_classname}"></i></div>
+                    <div class="callout-title-inner">{title}</div>
+                </div>"#
+        );
+
+        *out += r#"<div class="callout-content">"#;
+        cx.render_children(children, out);
+        *out += r#"</div></div>"#;
+    }
+
+    fn list(&self, node: List, out: &mut String, cx: &mut RenderContext) {
+        let tag_name = if node.ordered { "ol" } else { "ul" };
+        let start = node.start.unwrap_or(1);
+        *out += &format!(r#"<{tag_name} start="{start}">"#);
+        cx.render_children(node.children, out);
+        *out += &format!(r#"</{tag_name}>"#);
+    }
+
+    fn list_item(&self, node: ListItem, out: &mut String, cx: &mut RenderContext) {
+        *out += "<li>";
+        *out += match node.checked {
+            Some(true) => r#"<input type="checkbox" checked>"#,
+            Some(false) => r#"<input type="checkbox">"#,
+            None => "",
+        };
+        cx.render_children(node.children, out);
+        *out += "</li>";
+    }
+
+    fn paragraph(&self, node: Paragraph, out: &mut String, cx: &mut RenderContext) {
+        let mut children = node.children;
+        let mut had_block_id = false;
+        if let Some(Node::Text(Text { value, .. })) = children.last_mut() {
+            if let Some((text, _)) = split_block_id(value) {
+                *value = text.to_string();
+                had_block_id = true;
+            }
+        }
+
+        // A paragraph that was nothing but a `^block-id` has nothing left to show. Images
+        // have no text either, so only look for emptiness once the id has been removed.
+        let only_block_id = had_block_id
+            && children
+                .iter()
+                .all(|c| matches!(c, Node::Text(Text { value, .. }) if value.trim().is_empty()));
+
+        if is_lone_note_embed(&children) {
+            cx.render_children(children, out);
+        } else if !only_block_id {
+            cx.render_element("p", children, out);
+        }
+    }
+
+    fn heading(&self, node: Heading, out: &mut String, cx: &mut RenderContext) {
+        let Heading {
+            children,
+            depth,
+            position,
+        } = node;
+
+        let id = position
+            .and_then(|position| cx.definitions.take_heading_id(&position))
+            .unwrap_or_else(|| {
+                heading_slug(&children.iter().map(Node::to_string).collect::<String>())
+            });
+        *out += &format!(r#"<h{depth} id="{}">"#, escape_html_str(&id));
+        cx.render_children(children, out);
+        *out += &format!("</h{depth}>");
+    }
+
+    fn thematic_break(&self, _node: ThematicBreak, out: &mut String, _cx: &mut RenderContext) {
+        *out += "<hr>";
+    }
+
+    fn code(&self, node: Code, out: &mut String, cx: &mut RenderContext) {
+        let Code { lang, value, .. } = node;
+
+        let highlighted = match (&lang, cx.definitions.options.highlight) {
+            (Some(lang), HighlightMode::Static) => highlight_code(&value, lang),
+            _ => None,
+        };
+
+        let classname = match lang {
+            // Prism knows more languages than syntect, so let it have a go at the rest
+            Some(lang)
+                if highlighted.is_none()
+                    && cx.definitions.options.highlight == HighlightMode::Static =>
+            {
+                cx.definitions.needs_prism = true;
+                format!("language-{} {PRISM_FALLBACK_CLASS}", escape_html_str(&lang))
+            }
+            Some(lang) => format!("language-{}", escape_html_str(&lang)),
+            None => "".to_string(),
+        };
+
+        *out += "<pre><code class=\"";
+        *out += &classname;
+        *out += "\">";
+        *out += &highlighted.unwrap_or_else(|| escape_html_str(&value));
+        *out += "</code></pre>";
+    }
+
+    fn math(&self, node: Math, out: &mut String, cx: &mut RenderContext) {
+        if cx.definitions.options.math == MathMode::Static {
+            static_math_html(&node.value, true, node.position, out, &mut cx.definitions);
+            return;
+        }
+
+        *out += &format!(
+            "<div>{}</div><script>var target = document.currentScript.previousElementSibling;
+
+            katex.render(target.textContent, target, Object.assign({{
+                throwOnError: false,
+                displayMode: true
+            }}, window.katexOptions));</script>",
+            escape_html_str(&node.value)
+        );
+    }
+
+    /// HTML written straight into the note, sanitised if the options ask for it
+    fn html(&self, node: Html, out: &mut String, cx: &mut RenderContext) {
+        let options = cx.definitions.options;
+        if options.html == HtmlMode::Keep {
+            *out += &node.value;
+            return;
+        }
+
+        let (html, removed) = sanitize_html(&node.value, &options.html_allow_list);
+        if !removed.is_empty() {
+            cx.definitions.warn(
+                node.position,
+                format!("removed {} from raw HTML", removed.join(", ")),
+            );
+        }
+        *out += &html;
+    }
+
+    fn table(&self, node: Table, out: &mut String, cx: &mut RenderContext) {
+        let Table {
+            children, align, ..
+        } = node;
+        let mut rows = children.into_iter();
+
+        *out += "<table>";
+        if let Some(header) = rows.next() {
+            *out += "<thead>";
+            table_row_html(header, "th", &align, out, cx);
+            *out += "</thead>";
+        }
+        *out += "<tbody>";
+        for row in rows {
+            table_row_html(row, "td", &align, out, cx);
+        }
+        *out += "</tbody></table>";
+    }
+
+    /// A row outside of a table. Rows inside tables go through [`NodeHandler::table`].
+    fn table_row(&self, node: TableRow, out: &mut String, cx: &mut RenderContext) {
+        cx.render_element("tr", node.children, out);
+    }
+
+    /// A cell outside of a table. Cells inside tables go through [`NodeHandler::table`].
+    fn table_cell(&self, node: TableCell, out: &mut String, cx: &mut RenderContext) {
+        cx.render_element("td", node.children, out);
+    }
+
+    /// A `[id]: url` definition, which shows nothing itself
+    fn definition(&self, _node: Definition, _out: &mut String, _cx: &mut RenderContext) {}
+
+    /// An entry in the footnotes section at the end of the document, with a link back to
+    /// each of its references
+    fn footnote_definition(
+        &self,
+        node: FootnoteDefinition,
+        out: &mut String,
+        cx: &mut RenderContext,
+    ) {
+        let footnotes = &cx.definitions.footnotes;
+        let Some(number) = footnotes.number(cx.definitions.current_file(), &node.identifier) else {
+            return;
+        };
+        let references = footnotes.reference_count(number);
+
+        *out += &format!(r#"<li id="{}">"#, footnote_id(number));
+        cx.render_children(node.children, out);
+        for reference in 1..=references {
+            let id = reference_id(number, reference);
+            match reference {
+                1 => *out += &format!(r##"<a href="#{id}">↩</a>"##),
+                _ => *out += &format!(r##"<a href="#{id}">↩<sup>{reference}</sup></a>"##),
+            }
+        }
+        *out += "</li>";
+    }
+
+    /// A `[^id]` reference, shown as the number of the footnote it refers to
+    fn footnote_reference(
+        &self,
+        node: FootnoteReference,
+        out: &mut String,
+        cx: &mut RenderContext,
+    ) {
+        let FootnoteReference {
+            identifier,
+            label,
+            position,
+        } = node;
+
+        let file = cx.definitions.current_file().to_path_buf();
+        let Some((number, reference)) = cx.definitions.footnotes.next_reference(&file, &identifier)
+        else {
+            let label = label.unwrap_or(identifier);
+            cx.definitions
+                .warn(position, format!("no definition for footnote [^{label}]"));
+            *out += &escape_html_str(&format!("[^{label}]"));
+            return;
+        };
+
+        *out += &format!(
+            r##"<sup><a id="{}" href="#{}">[{number}]</a></sup>"##,
+            reference_id(number, reference),
+            footnote_id(number)
+        );
+    }
+
+    /// Plain text, along with the wikilinks, embeds, highlights and tags inside it
+    fn text(&self, node: Text, out: &mut String, cx: &mut RenderContext) {
+        let Text { value, position } = node;
+
+        for (start, end, message) in unmatched_references(&value) {
+            let position = sub_position(position.as_ref(), &value, start, end);
+            cx.definitions.warn(position, message);
+        }
+
+        for segment in split_wikilinks(&value) {
+            let TextSegment::Wikilink(link) = segment else {
+                if let TextSegment::Text(text) = segment {
+                    *out += &inline_html(text, cx.definitions.options);
+                }
+                continue;
+            };
+
+            let end = link.offset + link.source.len();
+            let position = sub_position(position.as_ref(), &value, link.offset, end);
+
+            if link.embed {
+                embed_html(&link, position, out, cx);
+            } else {
+                *out += &wikilink_html(&link, position, &mut cx.definitions);
+            }
+        }
+    }
+
+    fn emphasis(&self, node: Emphasis, out: &mut String, cx: &mut RenderContext) {
+        cx.render_element("em", node.children, out);
+    }
+
+    fn strong(&self, node: Strong, out: &mut String, cx: &mut RenderContext) {
+        cx.render_element("strong", node.children, out);
+    }
+
+    fn delete(&self, node: Delete, out: &mut String, cx: &mut RenderContext) {
+        cx.render_element("del", node.children, out);
+    }
+
+    fn inline_code(&self, node: InlineCode, out: &mut String, _cx: &mut RenderContext) {
+        *out += "<code>";
+        *out += &escape_html_str(&node.value);
+        *out += "</code>";
+    }
+
+    fn inline_math(&self, node: InlineMath, out: &mut String, cx: &mut RenderContext) {
+        if cx.definitions.options.math == MathMode::Static {
+            static_math_html(&node.value, false, node.position, out, &mut cx.definitions);
+            return;
+        }
+
+        *out += &format!(
+            "<span>{}</span><script>var target = document.currentScript.previousElementSibling;
+
+            katex.render(target.textContent, target, Object.assign({{
+                throwOnError: false,
+                displayMode: false
+            }}, window.katexOptions));</script>",
+            escape_html_str(&node.value)
+        );
+    }
+
+    fn line_break(&self, _node: Break, out: &mut String, _cx: &mut RenderContext) {
+        *out += "<br>";
+    }
+
+    fn link(&self, node: Link, out: &mut String, cx: &mut RenderContext) {
+        let Link {
+            children,
+            title,
+            url,
+            position,
+        } = node;
+        if cx.definitions.options.html == HtmlMode::Sanitize && !is_safe_url(&url) {
+            cx.definitions
+                .warn(position, format!("removed unsafe link to {url}"));
+            cx.render_children(children, out);
+            return;
+        }
+
+        let url = escape_html_str(&url);
+        let title = escape_html_str(&title.unwrap_or_default());
+
+        *out += &format!(r#"<a href="{url}" title="{title}">"#);
+        cx.render_children(children, out);
+        *out += "</a>";
+    }
+
+    fn image(&self, node: Image, out: &mut String, cx: &mut RenderContext) {
+        let Image {
+            alt,
+            url,
+            title,
+            position,
+        } = node;
+
+        if cx.definitions.options.html == HtmlMode::Sanitize && !is_safe_url(&url) {
+            cx.definitions
+                .warn(position, format!("removed unsafe image {url}"));
+            return;
+        }
+
+        let title = escape_html_str(&title.unwrap_or_default());
+        let (alt, size) = split_image_size(&alt);
+        let alt = escape_html_str(alt);
+        let size = size.map(|s| s.attributes()).unwrap_or_default();
+        let src = escape_html_str(&local_image_src(&url, position, &mut cx.definitions));
+
+        *out += &format!(r#"<img src="{src}" alt="{alt}" title="{title}"{size}/>"#);
+    }
+
+    /// A `[text][id]` link, rendered as the link its definition describes
+    fn link_reference(&self, node: LinkReference, out: &mut String, cx: &mut RenderContext) {
+        let LinkReference {
+            children,
+            identifier,
+            position,
+            ..
+        } = node;
+
+        // The parser only makes references whose label is defined. Undefined ones stay
+        // text, and NodeHandler::text warns about them.
+        let definition = cx.definitions.link_definitions.get(&identifier);
+        debug_assert!(definition.is_some(), "link reference without a definition");
+        let Some(Definition { url, title, .. }) = definition else {
+            cx.render_children(children, out);
+            return;
+        };
+
+        let link = Node::Link(Link {
+            children,
+            url: url.clone(),
+            title: title.clone(),
+            position,
+        });
+        cx.render(link, out);
+    }
+
+    /// A `![alt][id]` image, rendered as the image its definition describes
+    fn image_reference(&self, node: ImageReference, out: &mut String, cx: &mut RenderContext) {
+        let ImageReference {
+            alt,
+            identifier,
+            position,
+            ..
+        } = node;
+
+        let definition = cx.definitions.link_definitions.get(&identifier);
+        debug_assert!(definition.is_some(), "image reference without a definition");
+        let Some(Definition { url, title, .. }) = definition else {
+            *out += &escape_html_str(&alt);
+            return;
+        };
+
+        let image = Node::Image(Image {
+            alt,
+            url: url.clone(),
+            title: title.clone(),
+            position,
+        });
+        cx.render(image, out);
+    }
+
+    /// Any of the MDX node kinds, which only turn up if MDX parsing is switched on
+    fn mdx(&self, node: Node, _out: &mut String, cx: &mut RenderContext) {
+        cx.definitions.error(
+            node.position().cloned(),
+            "MDX is not supported by gh-canvas",
+        );
+    }
+}
+
+/// Renders every node the usual way
+pub struct DefaultHandler;
+
+impl NodeHandler for DefaultHandler {}
+
+/// What a [`NodeHandler`] renders with: everything known about the document so far, and
+/// the handler to render child nodes with
+pub struct RenderContext<'a> {
+    pub definitions: Definitions<'a>,
+    handler: &'a dyn NodeHandler,
+}
+
+impl<'a> RenderContext<'a> {
+    pub fn new(definitions: Definitions<'a>, handler: &'a dyn NodeHandler) -> Self {
+        RenderContext {
+            definitions,
+            handler,
+        }
+    }
+
+    pub fn handler(&self) -> &'a dyn NodeHandler {
+        self.handler
+    }
+
+    /// Render a node with the handler's method for its kind
+    pub fn render(&mut self, node: Node, out: &mut String) {
+        let handler = self.handler;
+
+        if let Some(levels) = toc_marker(&node) {
+            return handler.toc(levels, out, self);
+        }
+        // Before the HTML handler, which might sanitise the comment away
+        if page_break_marker(&node, self.definitions.options.page_break_on_rule) {
+            return handler.page_break(out, self);
+        }
+
+        match node {
+            // Both are gathered up before rendering, so the properties block can go first
+            // and the footnotes last
+            Node::Yaml(_) | Node::FootnoteDefinition(_) => {}
+            Node::Root(node) => handler.root(node, out, self),
+            Node::Toml(node) => handler.toml(node, out, self),
+            Node::BlockQuote(node) => handler.block_quote(node, out, self),
+            Node::List(node) => handler.list(node, out, self),
+            Node::ListItem(node) => handler.list_item(node, out, self),
+            Node::Paragraph(node) => handler.paragraph(node, out, self),
+            Node::Heading(node) => handler.heading(node, out, self),
+            Node::ThematicBreak(node) => handler.thematic_break(node, out, self),
+            Node::Code(node) => handler.code(node, out, self),
+            Node::Math(node) => handler.math(node, out, self),
+            Node::Html(node) => handler.html(node, out, self),
+            Node::Table(node) => handler.table(node, out, self),
+            Node::TableRow(node) => handler.table_row(node, out, self),
+            Node::TableCell(node) => handler.table_cell(node, out, self),
+            Node::Definition(node) => handler.definition(node, out, self),
+            Node::FootnoteReference(node) => handler.footnote_reference(node, out, self),
+            Node::Text(node) => handler.text(node, out, self),
+            Node::Emphasis(node) => handler.emphasis(node, out, self),
+            Node::Strong(node) => handler.strong(node, out, self),
+            Node::Delete(node) => handler.delete(node, out, self),
+            Node::InlineCode(node) => handler.inline_code(node, out, self),
+            Node::InlineMath(node) => handler.inline_math(node, out, self),
+            Node::Break(node) => handler.line_break(node, out, self),
+            Node::Link(node) => handler.link(node, out, self),
+            Node::Image(node) => handler.image(node, out, self),
+            Node::LinkReference(node) => handler.link_reference(node, out, self),
+            Node::ImageReference(node) => handler.image_reference(node, out, self),
+            node @ (Node::MdxJsxTextElement(_)
+            | Node::MdxTextExpression(_)
+            | Node::MdxFlowExpression(_)
+            | Node::MdxJsxFlowElement(_)
+            | Node::MdxjsEsm(_)) => handler.mdx(node, out, self),
+        }
+    }
+
+    pub fn render_children(&mut self, children: Vec<Node>, out: &mut String) {
+        for child in children {
+            self.render(child, out);
+        }
+    }
+
+    /// Render children wrapped in an element with no attributes
+    pub fn render_element(&mut self, element: &str, children: Vec<Node>, out: &mut String) {
+        *out += &format!("<{element}>");
+        self.render_children(children, out);
+        *out += &format!("</{element}>");
+    }
+}
@@ -0,0 +1,227 @@
+use std::{
+    path::Path,
+    process::Command,
+    sync::OnceLock,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use regex::Regex;
+use serde::Deserialize;
+use serde_yaml::Value;
+
+use crate::{
+    obsidian_vault::ObsidianTheme,
+    page::Margins,
+    properties::{long_date, scalar_text},
+};
+
+/// What goes in the left, centre and right of a page's header or footer. Each is text
+/// with `{placeholders}`: `{page}` and `{pages}` for the page number and page count,
+/// `{date}` for today's date, `{hash}` for the note's git commit, and any frontmatter key,
+/// like `{title}` or `{author}`.
+#[derive(Deserialize, Clone, Debug, Default)]
+#[serde(deny_unknown_fields)]
+pub struct RunningText {
+    pub left: Option<String>,
+    pub center: Option<String>,
+    pub right: Option<String>,
+}
+
+impl RunningText {
+    pub fn is_empty(&self) -> bool {
+        self.left.is_none() && self.center.is_none() && self.right.is_none()
+    }
+
+    /// Set whichever slots `other` sets
+    pub fn merge(&mut self, other: RunningText) {
+        self.left = other.left.or(self.left.take());
+        self.center = other.center.or(self.center.take());
+        self.right = other.right.or(self.right.take());
+    }
+
+    fn slots(&self) -> [(&'static str, &Option<String>); 3] {
+        [
+            ("left", &self.left),
+            ("center", &self.center),
+            ("right", &self.right),
+        ]
+    }
+}
+
+/// `@page` margin boxes for the header and footer, along with any placeholders nothing
+/// could be found for. The page has no side margins of its own, so the left and right
+/// boxes are padded in by their side's margin to line up with the text, and the centre
+/// boxes aren't padded at all. With `first_page` unset, the first page has neither.
+pub(crate) fn header_footer_css(
+    header: &RunningText,
+    footer: &RunningText,
+    margins: &Margins,
+    theme: ObsidianTheme,
+    first_page: bool,
+    frontmatter: Option<&Value>,
+    file: &Path,
+) -> (String, Vec<String>) {
+    if header.is_empty() && footer.is_empty() {
+        return (String::new(), Vec::new());
+    }
+
+    let mut values = PlaceholderValues {
+        frontmatter,
+        file,
+        hash: OnceLock::new(),
+        unknown: Vec::new(),
+    };
+    let Margins { right, left, .. } = margins;
+    let color = match theme {
+        ObsidianTheme::Light => "#555",
+        ObsidianTheme::Dark => "#aaa",
+    };
+    let mut boxes = String::new();
+    let mut first_page_boxes = String::new();
+
+    for (edge, text) in [("top", header), ("bottom", footer)] {
+        for (slot, template) in text.slots() {
+            let Some(template) = template else {
+                continue;
+            };
+
+            let padding = match slot {
+                "left" => format!("0 0 0 {left}"),
+                "right" => format!("0 {right} 0 0"),
+                _ => "0".to_string(),
+            };
+            boxes += &format!(
+                "@{edge}-{slot} {{ content: {}; padding: {padding}; font-family: Inter, sans-serif; font-size: 9pt; color: {color}; }}\n",
+                css_content(template, &mut values)
+            );
+            first_page_boxes += &format!("@{edge}-{slot} {{ content: none; }}\n");
+        }
+    }
+
+    let mut css = format!("@page {{\n{boxes}}}\n");
+    if !first_page {
+        css += &format!("@page:first {{\n{first_page_boxes}}}\n");
+    }
+
+    (css, values.unknown)
+}
+
+struct PlaceholderValues<'a> {
+    frontmatter: Option<&'a Value>,
+    file: &'a Path,
+    hash: OnceLock<Option<String>>,
+    unknown: Vec<String>,
+}
+
+impl PlaceholderValues<'_> {
+    fn get(&mut self, name: &str) -> String {
+        let value = match name {
+            "date" => Some(today()),
+            "hash" => self.hash.get_or_init(|| git_short_hash(self.file)).clone(),
+            name => match self.frontmatter.and_then(|f| f.get(name)) {
+                Some(value) => Some(match value {
+                    Value::Sequence(items) => {
+                        items.iter().map(scalar_text).collect::<Vec<_>>().join(", ")
+                    }
+                    value => scalar_text(value),
+                }),
+                None if name == "title" => Some(
+                    self.file
+                        .file_stem()
+                        .unwrap_or_default()
+                        .to_string_lossy()
+                        .into(),
+                ),
+                None => None,
+            },
+        };
+
+        value.unwrap_or_else(|| {
+            if !self.unknown.iter().any(|u| u == name) {
+                self.unknown.push(name.to_string());
+            }
+            String::new()
+        })
+    }
+}
+
+/// A `content` value for a margin box: the text as CSS strings, with the page number and
+/// page count as counters
+fn css_content(template: &str, values: &mut PlaceholderValues) -> String {
+    static PLACEHOLDER: OnceLock<Regex> = OnceLock::new();
+    let placeholder = PLACEHOLDER
+        .get_or_init(|| Regex::new(r"\{\s*([\w-]+)\s*\}").expect("placeholder regex is valid"));
+
+    let mut parts = Vec::new();
+    let mut text = String::new();
+    let mut rest = 0;
+
+    for captures in placeholder.captures_iter(template) {
+        let whole = captures.get(0).expect("the whole match is always there");
+        text += &template[rest..whole.start()];
+        rest = whole.end();
+
+        match &captures[1] {
+            counter @ ("page" | "pages") => {
+                parts.push(css_string(&std::mem::take(&mut text)));
+                parts.push(format!("counter({counter})"));
+            }
+            name => text += &values.get(name),
+        }
+    }
+    text += &template[rest..];
+    parts.push(css_string(&text));
+
+    parts.retain(|part| part != r#""""#);
+    match parts.is_empty() {
+        true => r#""""#.to_string(),
+        false => parts.join(" "),
+    }
+}
+
+/// Text as a quoted CSS string that can't end the string, or the `<style>` it's in, early
+fn css_string(text: &str) -> String {
+    let mut css = String::from('"');
+    for c in text.chars() {
+        match c {
+            '"' | '\\' | '<' | '>' | '\n' | '\r' => css += &format!("\\{:x} ", c as u32),
+            c => css.push(c),
+        }
+    }
+    css.push('"');
+    css
+}
+
+/// The short hash of the commit checked out in the note's repository
+fn git_short_hash(file: &Path) -> Option<String> {
+    let output = Command::new("git")
+        .arg("-C")
+        .arg(file.parent()?)
+        .args(["rev-parse", "--short", "HEAD"])
+        .output()
+        .ok()?;
+
+    let hash = String::from_utf8(output.stdout).ok()?.trim().to_string();
+    (output.status.success() && !hash.is_empty()).then_some(hash)
+}
+
+/// Today's date in words, in UTC
+fn today() -> String {
+    let days = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs() / 86_400) as i64;
+
+    // Days since 1970-01-01 to a civil date, from Howard Hinnant's `civil_from_days`
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1_460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+
+    let iso = format!("{year:04}-{month:02}-{day:02}");
+    long_date(&iso, false).unwrap_or(iso)
+}
@@ -0,0 +1,136 @@
+use std::collections::HashSet;
+
+use markdown::mdast::{Code, Node, Paragraph, Text};
+
+use crate::ast_to_html::escape_html_str;
+
+/// Anchor id for a heading, slugged the way GitHub and Obsidian's publish do it:
+/// lowercased, spaces to hyphens, and punctuation other than `-` and `_` dropped
+pub fn heading_slug(heading: &str) -> String {
+    heading
+        .trim()
+        .to_lowercase()
+        .chars()
+        .filter_map(|c| match c {
+            c if c.is_whitespace() => Some('-'),
+            c if c.is_alphanumeric() || c == '-' || c == '_' => Some(c),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Hands out heading ids, suffixing repeats with `-1`, `-2`, ... like GitHub does
+#[derive(Default)]
+pub struct HeadingIds(HashSet<String>);
+
+impl HeadingIds {
+    pub fn unique(&mut self, slug: &str) -> String {
+        let slug = if slug.is_empty() { "section" } else { slug };
+
+        let mut id = slug.to_string();
+        let mut n = 0;
+        // A heading like "Intro 1" can already have taken "intro-1", so keep counting
+        while self.0.contains(&id) {
+            n += 1;
+            id = format!("{slug}-{n}");
+        }
+
+        self.0.insert(id.clone());
+        id
+    }
+}
+
+pub struct TocEntry {
+    pub depth: u8,
+    pub text: String,
+    pub id: String,
+}
+
+/// Heading levels a table of contents covers, inclusive
+pub struct TocLevels {
+    pub min: u8,
+    pub max: u8,
+}
+
+impl Default for TocLevels {
+    fn default() -> Self {
+        TocLevels { min: 1, max: 6 }
+    }
+}
+
+/// Whether a node asks for a table of contents in its place: a `[TOC]` paragraph, or a
+/// `toc` code block like the Automatic Table of Contents plugin uses
+pub fn toc_marker(node: &Node) -> Option<TocLevels> {
+    match node {
+        Node::Paragraph(Paragraph { children, .. }) => match children.as_slice() {
+            [Node::Text(Text { value, .. })] if value.trim().eq_ignore_ascii_case("[toc]") => {
+                Some(TocLevels::default())
+            }
+            _ => None,
+        },
+        Node::Code(Code {
+            lang: Some(lang),
+            value,
+            ..
+        }) if lang == "toc" => {
+            let mut levels = TocLevels::default();
+
+            for line in value.lines() {
+                let Some((key, level)) = line.split_once(':') else {
+                    continue;
+                };
+                // The plugin uses 0 for "no limit"
+                match (key.trim(), level.trim().parse()) {
+                    ("minLevel", Ok(level)) if level > 0 => levels.min = level,
+                    ("maxLevel", Ok(level)) if level > 0 => levels.max = level,
+                    _ => {}
+                }
+            }
+
+            Some(levels)
+        }
+        _ => None,
+    }
+}
+
+/// A nested list of links to the document's headings
+pub fn toc_html(entries: &[TocEntry], levels: &TocLevels) -> String {
+    let mut entries = entries
+        .iter()
+        .filter(|e| e.depth >= levels.min && e.depth <= levels.max)
+        .peekable();
+    if entries.peek().is_none() {
+        return String::new();
+    }
+
+    let mut html = String::from(r#"<nav class="table-of-contents">"#);
+    let mut open: Vec<u8> = Vec::new();
+
+    for entry in entries {
+        while open.last().is_some_and(|&depth| depth > entry.depth) {
+            open.pop();
+            html += "</li></ul>";
+        }
+
+        match open.last() {
+            Some(&depth) if depth == entry.depth => html += "</li>",
+            _ => {
+                open.push(entry.depth);
+                html += "<ul>";
+            }
+        }
+
+        html += &format!(
+            r##"<li><a class="internal-link" href="#{}">{}</a>"##,
+            entry.id,
+            escape_html_str(&entry.text)
+        );
+    }
+
+    for _ in open {
+        html += "</li></ul>";
+    }
+
+    html += "</nav>";
+    html
+}
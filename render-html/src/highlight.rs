@@ -0,0 +1,168 @@
+use std::sync::OnceLock;
+
+use clap::ValueEnum;
+use serde::Deserialize;
+use syntect::parsing::{ParseState, Scope, ScopeStack, ScopeStackOp, SyntaxReference, SyntaxSet};
+
+use crate::ast_to_html::escape_html_str;
+
+/// How code blocks get their syntax highlighting
+#[derive(ValueEnum, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum HighlightMode {
+    /// Tokenise while rendering, so highlighting needs no JavaScript
+    Static,
+    /// Leave it to the embedded Prism script when Chrome loads the page
+    Script,
+}
+
+pub const PRISM_JS: &str = include_str!("./asset/prism.js");
+
+/// The class of code blocks left for Prism because syntect doesn't know their language
+pub const PRISM_FALLBACK_CLASS: &str = "prism-fallback";
+
+/// Prism, set to only highlight the code blocks static highlighting couldn't. Goes at the
+/// end of the body, once those blocks are in the page.
+pub fn prism_fallback_html() -> String {
+    format!(
+        r#"<script data-manual>{PRISM_JS}</script><script>document.querySelectorAll("code.{PRISM_FALLBACK_CLASS}").forEach((code) => Prism.highlightElement(code));</script>"#
+    )
+}
+
+fn syntax_set() -> &'static SyntaxSet {
+    static SYNTAXES: OnceLock<SyntaxSet> = OnceLock::new();
+    SYNTAXES.get_or_init(SyntaxSet::load_defaults_newlines)
+}
+
+fn find_syntax(lang: &str) -> Option<&'static SyntaxReference> {
+    let lang = lang.to_lowercase();
+
+    // Names Prism and Obsidian accept that Sublime's syntaxes don't know
+    let token = match lang.as_str() {
+        "shell" | "console" | "shellsession" | "zsh" => "bash",
+        "jsx" | "ts" | "tsx" | "typescript" | "mjs" | "cjs" => "js",
+        "csharp" | "c#" => "cs",
+        "golang" => "go",
+        "latex" => "tex",
+        "objc" | "objectivec" => "m",
+        "htm" | "svg" | "markup" => "html",
+        lang => lang,
+    };
+
+    syntax_set().find_syntax_by_token(token)
+}
+
+/// Tokenise code into spans with Prism's `token <kind>` class names, which Obsidian
+/// themes already style. `None` if the language isn't one we can highlight.
+pub fn highlight_code(code: &str, lang: &str) -> Option<String> {
+    let syntaxes = syntax_set();
+    let syntax = find_syntax(lang)?;
+
+    let mut parse_state = ParseState::new(syntax);
+    let mut scopes = ScopeStack::new();
+    let mut tokens = Tokens::default();
+
+    for line in code.split_inclusive('\n') {
+        let Ok(ops) = parse_state.parse_line(line, syntaxes) else {
+            tokens.push(line, None);
+            continue;
+        };
+
+        let mut start = 0;
+        for (end, op) in ops.iter().chain([&(line.len(), ScopeStackOp::Noop)]) {
+            if *end > start {
+                tokens.push(&line[start..*end], token_kind(&scopes));
+                start = *end;
+            }
+            // Ignore malformed stacks rather than stopping highlighting part way
+            let _ = scopes.apply(op);
+        }
+    }
+
+    Some(tokens.finish())
+}
+
+/// Highlighted HTML, built up so that neighbouring text of the same kind shares a span
+#[derive(Default)]
+struct Tokens {
+    html: String,
+    pending: String,
+    pending_kind: Option<&'static str>,
+}
+
+impl Tokens {
+    fn push(&mut self, text: &str, kind: Option<&'static str>) {
+        if kind != self.pending_kind {
+            self.flush();
+            self.pending_kind = kind;
+        }
+        self.pending += text;
+    }
+
+    fn flush(&mut self) {
+        let text = escape_html_str(&self.pending);
+        match self.pending_kind {
+            Some(kind) if !text.is_empty() => {
+                self.html += &format!(r#"<span class="token {kind}">{text}</span>"#)
+            }
+            _ => self.html += &text,
+        }
+        self.pending.clear();
+    }
+
+    fn finish(mut self) -> String {
+        self.flush();
+        self.html
+    }
+}
+
+/// The Prism token kind for the innermost scope that has one
+fn token_kind(scopes: &ScopeStack) -> Option<&'static str> {
+    scopes.as_slice().iter().rev().find_map(prism_token_kind)
+}
+
+fn prism_token_kind(scope: &Scope) -> Option<&'static str> {
+    let name = scope.build_string();
+
+    // Quotes and comment markers belong to the string or comment around them
+    if name.starts_with("punctuation.definition") {
+        return None;
+    }
+
+    const KINDS: [(&str, &str); 29] = [
+        ("comment", "comment"),
+        ("string.regexp", "regex"),
+        ("string", "string"),
+        ("constant.numeric", "number"),
+        ("constant.language.boolean", "boolean"),
+        ("constant.character.escape", "char"),
+        ("constant", "constant"),
+        ("support.constant", "constant"),
+        ("keyword.operator", "operator"),
+        ("keyword", "keyword"),
+        ("storage", "keyword"),
+        ("entity.name.function", "function"),
+        ("support.function", "function"),
+        ("variable.function", "function"),
+        ("entity.name.namespace", "namespace"),
+        ("entity.name.tag", "tag"),
+        ("entity.other.attribute-name", "attr-name"),
+        ("entity.name", "class-name"),
+        ("entity.other.inherited-class", "class-name"),
+        ("support.type", "class-name"),
+        ("support.class", "class-name"),
+        ("variable.language", "keyword"),
+        ("variable.parameter", "parameter"),
+        ("punctuation", "punctuation"),
+        ("markup.heading", "title"),
+        ("markup.bold", "bold"),
+        ("markup.italic", "italic"),
+        ("markup.inserted", "inserted"),
+        ("markup.deleted", "deleted"),
+    ];
+
+    KINDS
+        .iter()
+        .find(|(prefix, _)| name == *prefix || name.starts_with(&format!("{prefix}.")))
+        .map(|(_, kind)| *kind)
+}
@@ -0,0 +1,104 @@
+use std::path::Path;
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+
+/// A `|300` or `|300x200` size suffix from an image embed or image alt text
+pub struct ImageSize {
+    pub width: u32,
+    pub height: Option<u32>,
+}
+
+impl ImageSize {
+    pub fn parse(s: &str) -> Option<ImageSize> {
+        let (width, height) = match s.trim().split_once('x') {
+            Some((width, height)) => (width, Some(height.parse().ok()?)),
+            None => (s.trim(), None),
+        };
+
+        Some(ImageSize {
+            width: width.parse().ok()?,
+            height,
+        })
+    }
+
+    pub fn attributes(&self) -> String {
+        match self.height {
+            Some(height) => format!(r#" width="{}" height="{height}""#, self.width),
+            None => format!(r#" width="{}""#, self.width),
+        }
+    }
+}
+
+/// Split `alt text|300x200` into the alt text and its size suffix, if it has one
+pub fn split_image_size(alt: &str) -> (&str, Option<ImageSize>) {
+    match alt.rsplit_once('|') {
+        Some((text, size)) => match ImageSize::parse(size) {
+            Some(size) => (text, Some(size)),
+            None => (alt, None),
+        },
+        None => match ImageSize::parse(alt) {
+            Some(size) => ("", Some(size)),
+            None => (alt, None),
+        },
+    }
+}
+
+pub fn is_remote_url(url: &str) -> bool {
+    url.starts_with("data:") || url.starts_with("//") || url.contains("://")
+}
+
+pub fn mime_type(path: &Path) -> Option<&'static str> {
+    let extension = path.extension()?.to_string_lossy().to_lowercase();
+
+    Some(match extension.as_str() {
+        "png" => "image/png",
+        "jpg" | "jpeg" => "image/jpeg",
+        "gif" => "image/gif",
+        "bmp" => "image/bmp",
+        "svg" => "image/svg+xml",
+        "webp" => "image/webp",
+        "avif" => "image/avif",
+        _ => return None,
+    })
+}
+
+/// Read an image into a `data:` URI, so the rendered HTML doesn't depend on the
+/// directory Chrome is run from. Files that aren't a known kind of image are refused.
+pub fn image_data_uri(path: &Path) -> std::io::Result<String> {
+    let Some(mime) = mime_type(path) else {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            "not a kind of image that can be inlined",
+        ));
+    };
+    let bytes = std::fs::read(path)?;
+
+    Ok(format!("data:{mime};base64,{}", STANDARD.encode(bytes)))
+}
+
+/// Undo `%20`-style escapes in a Markdown image path
+pub fn percent_decode(url: &str) -> String {
+    let bytes = url.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+
+    while i < bytes.len() {
+        let hex = bytes
+            .get(i + 1..i + 3)
+            .and_then(|hex| std::str::from_utf8(hex).ok())
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+
+        match (bytes[i], hex) {
+            (b'%', Some(byte)) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            (byte, _) => {
+                decoded.push(byte);
+                i += 1;
+            }
+        }
+    }
+
+    String::from_utf8_lossy(&decoded).into_owned()
+}
@@ -0,0 +1,163 @@
+use markdown::mdast::{Node, Text};
+
+use crate::{ast_to_html::escape_html_str, options::RenderOptions};
+
+/// Remove `%% comments %%` from a note. Comments can span paragraphs, so whatever falls
+/// between an opening and closing `%%` is dropped, and blocks left empty go with it.
+pub fn strip_comments(ast: &mut Node) {
+    strip_comments_from(ast, &mut false);
+}
+
+/// Whether the node still has anything to render once comments are gone
+fn strip_comments_from(ast: &mut Node, in_comment: &mut bool) -> bool {
+    if let Node::Text(Text { value, .. }) = ast {
+        if !*in_comment && !value.contains("%%") {
+            return true;
+        }
+        *value = strip_comment_text(value, in_comment);
+        return !value.is_empty();
+    }
+
+    let started_in_comment = *in_comment;
+    let Some(children) = ast.children_mut() else {
+        return !*in_comment;
+    };
+
+    let count = children.len();
+    children.retain_mut(|child| strip_comments_from(child, in_comment));
+
+    // Keep nodes that were empty to begin with, like empty list items
+    !children.is_empty() || (count == 0 && !started_in_comment)
+}
+
+fn strip_comment_text(text: &str, in_comment: &mut bool) -> String {
+    let mut result = String::new();
+
+    for (i, part) in text.split("%%").enumerate() {
+        if i > 0 {
+            *in_comment = !*in_comment;
+        }
+        if !*in_comment {
+            result += part;
+        }
+    }
+
+    // A comment on its own lines shouldn't leave blank lines behind
+    match result.trim().is_empty() {
+        true => String::new(),
+        false => result,
+    }
+}
+
+/// Text as HTML, with `==highlights==` and `#tags` turned into the elements Obsidian
+/// gives them, as far as the options allow
+pub fn inline_html(text: &str, options: &RenderOptions) -> String {
+    let mut html = String::new();
+    let mut rest = text;
+
+    while let Some((before, marked, after)) = split_mark(rest).filter(|_| options.marks) {
+        html += &tags_html(before, options);
+        html += &format!("<mark>{}</mark>", tags_html(marked, options));
+        rest = after;
+    }
+
+    html += &tags_html(rest, options);
+    html
+}
+
+/// Split `before ==marked== after`, if the text has a highlight. Like Obsidian, the marked
+/// text can't start or end with whitespace, so an `==` that can't open or close a
+/// highlight is passed over and the search carries on after it.
+fn split_mark(text: &str) -> Option<(&str, &str, &str)> {
+    let mut search = 0;
+
+    while let Some(found) = text[search..].find("==") {
+        let start = search + found;
+        let rest = &text[start + 2..];
+
+        if rest.starts_with(|c: char| !c.is_whitespace() && c != '=') {
+            // Any later opener would need one of the same closers, so there's no point
+            // looking further if this one has none
+            let end = closing_mark(rest)?;
+            return Some((&text[..start], &rest[..end], &rest[end + 2..]));
+        }
+        search = start + 2;
+    }
+
+    None
+}
+
+/// Where the `==` closing a highlight is, given the text after its opening `==`
+fn closing_mark(text: &str) -> Option<usize> {
+    text.match_indices("==")
+        .map(|(end, _)| end)
+        .find(|&end| end > 0 && !text[..end].ends_with(char::is_whitespace))
+}
+
+fn tags_html(text: &str, options: &RenderOptions) -> String {
+    if !options.tags {
+        return escape_html_str(text);
+    }
+
+    let mut html = String::new();
+    let mut plain_start = 0;
+    let mut previous = None;
+
+    for (i, c) in text.char_indices() {
+        let at_boundary = previous.is_none_or(char::is_whitespace);
+        previous = Some(c);
+
+        if c != '#' || !at_boundary || i < plain_start {
+            continue;
+        }
+
+        let tag_len = text[i + 1..]
+            .find(|c: char| !is_tag_char(c))
+            .unwrap_or(text.len() - i - 1);
+        let tag = &text[i + 1..i + 1 + tag_len];
+
+        // Obsidian doesn't count `#123` as a tag
+        if tag.is_empty() || tag.chars().all(|c| c.is_ascii_digit()) {
+            continue;
+        }
+
+        html += &escape_html_str(&text[plain_start..i]);
+        let tag = escape_html_str(tag);
+        html += &format!(r##"<a href="#{tag}" class="tag">#{tag}</a>"##);
+        plain_start = i + 1 + tag_len;
+    }
+
+    html += &escape_html_str(&text[plain_start..]);
+    html
+}
+
+fn is_tag_char(c: char) -> bool {
+    c.is_alphanumeric() || matches!(c, '_' | '-' | '/')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn marks(text: &str) -> String {
+        inline_html(text, &RenderOptions::default())
+    }
+
+    #[test]
+    fn skips_an_equals_sign_that_cant_open_a_highlight() {
+        assert_eq!(marks("x == y and ==c=="), "x == y and <mark>c</mark>");
+    }
+
+    #[test]
+    fn skips_an_equals_sign_that_cant_close_a_highlight() {
+        assert_eq!(
+            marks("==a == b== and ==c=="),
+            "<mark>a == b</mark> and <mark>c</mark>"
+        );
+    }
+
+    #[test]
+    fn needs_text_right_inside_the_marks() {
+        assert_eq!(marks("== a== and ==b =="), "== a== and ==b ==");
+    }
+}
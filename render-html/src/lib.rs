@@ -0,0 +1,41 @@
+#![allow(clippy::needless_return)]
+
+mod assets;
+mod ast_to_html;
+mod callout;
+mod cover;
+mod diagnostics;
+mod embed;
+mod footnotes;
+mod handler;
+mod header_footer;
+mod headings;
+mod highlight;
+mod image;
+mod inline;
+mod math;
+mod obsidian_style_settings;
+mod obsidian_vault;
+mod options;
+mod page;
+mod properties;
+mod renderer;
+mod sanitize;
+mod wikilink;
+
+pub use crate::{
+    assets::AssetMode,
+    ast_to_html::Definitions,
+    cover::CoverLayout,
+    diagnostics::{Diagnostic, RenderError, Severity},
+    handler::{DefaultHandler, NodeHandler, RenderContext},
+    header_footer::RunningText,
+    headings::TocLevels,
+    highlight::HighlightMode,
+    math::MathMode,
+    obsidian_vault::{ObsidianTheme, ObsidianVault, ThemeVariant},
+    options::RenderOptions,
+    page::{Length, Margins, Orientation, PageGeometry, PageSize},
+    renderer::{DocumentCss, RenderedDocument, Renderer},
+    sanitize::{HtmlAllowList, HtmlMode},
+};
@@ -1,150 +1,145 @@
-mod ast_to_html;
-mod obsidian_style_settings;
-mod obsidian_vault;
-
-use std::path::PathBuf;
+use std::{
+    fs::File,
+    io::{self, BufWriter, Write},
+    path::PathBuf,
+    process::ExitCode,
+};
 
-use anyhow::Context;
 use clap::Parser;
-
-use crate::{
-    obsidian_style_settings::StyleSettingsCss,
-    obsidian_vault::{ObsidianTheme::Light, ObsidianVault},
+use render_html::{
+    AssetMode, CoverLayout, HighlightMode, HtmlAllowList, HtmlMode, Length, Margins, MathMode,
+    Orientation, PageSize, RenderError, RenderOptions, Renderer, RunningText, Severity,
+    ThemeVariant,
 };
 
-const DEFAULT_FONT_SIZE: i32 = 18;
-const DEFAULT_ZOOM_FACTOR: f64 = 1.; //0.9128709291752769;
-const DEFAULT_MONO_FONT: &'static str = "Fira Code Retina";
-const DEFAULT_H1_WEIGHT: u32 = 800;
-const DEFAULT_H2_WEIGHT: u32 = 800;
-
-fn main() -> Result<(), Box<dyn std::error::Error>> {
+fn main() -> ExitCode {
     let args = CliArgs::parse();
 
-    let font_size = args.font_size.unwrap_or(DEFAULT_FONT_SIZE);
-    let zoom_factor = args.zoom_factor.unwrap_or(DEFAULT_ZOOM_FACTOR);
-    let mono_font = args.mono_font.unwrap_or(DEFAULT_MONO_FONT.into());
-    let h1_weight = args.h1_weight.unwrap_or(DEFAULT_H1_WEIGHT);
-    let h2_weight = args.h2_weight.unwrap_or(DEFAULT_H2_WEIGHT);
-
-    let file = std::fs::canonicalize(args.file)?;
-
-    let input_md = std::fs::read_to_string(&file)
-        .with_context(|| format!("Couldn't read Markdown from {}", file.to_string_lossy()))?;
-
-    let body = html_body_of_md(&input_md);
-
-    let vault =
-        ObsidianVault::vault_of_file(&file)?.ok_or("Couldn't find Obsidian vault folder")?;
-
-    let app_css = include_str!("./asset/app.css");
-    let properties_css = include_str!("./asset/properties.css");
-
-    let prism_js = include_str!("./asset/prism.js");
-
-    let StyleSettingsCss {
-        theme_css,
-        style_overrides,
-        body_classes,
-    } = vault.style_css(&Light)?.unwrap_or_default();
-
-    let html_total = format!(
-        r#"<!DOCTYPE html>
-    <html>
-    <head>
-        <meta charset="UTF-8"/>
-        <style>
-            {app_css}
-        </style>
-        <style>
-            {theme_css}
-        </style>
-        <style>
-            {style_overrides}
-        </style>
-
-        <style>
-            {properties_css}
-        </style>
-
-        <style>
-            :root {{
-                overflow: unset;
-            }}
-            .markdown-preview-view {{
-                overflow: unset;
-            }}
-            body {{
-                overflow: unset;
-                --file-margins: 0;
-                --background-primary: #fff !important;
-            }}
-            body.theme-light {{
-                --h1-weight: {h1_weight};
-                --h2-weight: {h2_weight};
-            }}
-        </style>
-
-        <style>
-            @page {{
-                margin: 0;
-                margin-bottom: 0.65in;
-                margin-top: 0.65in;
-                padding: 0;
-                size: 8.5in 11in;
-            }}
-            @page:first {{
-                margin-top: 0
-            }}
-            .non-meta-content {{
-                margin: 0;
-                margin-left: 0.65in;
-                margin-right: 0.65in;
-                margin-top: 0.65in;
-            }}
-            .properties ~ .non-meta-content {{
-                margin-top: var(--spacing-p);
-            }}
-        </style>
-
-        <!-- KaTeX (for math)! -->
-        <link rel="stylesheet" href="https://cdn.jsdelivr.net/npm/katex@0.16.9/dist/katex.min.css" integrity="sha384-n8MVd4RsNIU0tAv4ct0nTaAbDJwPJzDEaqSD1odI+WdtXRGWt2kTvGFasHpSy3SV" crossorigin="anonymous">
-        <script src="https://cdn.jsdelivr.net/npm/katex@0.16.9/dist/katex.min.js" integrity="sha384-XjKyOOlGwcjNTAIQHIpgOno0Hl1YQqzUOEleOLALmuqehneUG+vnGctmUb0ZY0l8" crossorigin="anonymous"></script>
-
-        <!-- Prism (for syntax highlighting)! -->
-        <script>{prism_js}</script>
-
-        <!-- Lucide (for icons)! -->
-        <style>
-        @font-face {{
-            font-family: 'LucideIcons';
-            src: url(https://unpkg.com/lucide-static@latest/font/Lucide.ttf) format('truetype');
-        }}
-        </style>
+    match render(args) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("{e}");
+            ExitCode::FAILURE
+        }
+    }
+}
 
-        <link rel='preconnect' href='https://fonts.googleapis.com'>
-        <link rel='preconnect' href='https://fonts.gstatic.com' crossorigin>
-        <link href='https://fonts.googleapis.com/css2?family=Inter:wght@100;200;300;400;500;600;700;800;900&display=block' rel='stylesheet'>
-    </head>
-    <body class='{body_classes}' style="--font-text-size: {font_size}px; --zoom-factor: {zoom_factor}; --font-monospace-override: &quot;{mono_font}&quot;;">
-        <div class="print">
-            <div class="markdown-rendered markdown-preview-view show-properties">
-                {body}
-            </main>
-        </div>
-    </body>
-    </html>
-    "#
+/// Render the note to the output, printing any diagnostics. Fails if any of them are
+/// errors, in which case an output file is removed rather than left half-written.
+fn render(args: CliArgs) -> Result<(), RenderError> {
+    let defaults = RenderOptions::default();
+    let mut page = defaults.page.clone();
+    page.apply(
+        args.page_size,
+        args.orientation,
+        args.margin,
+        [
+            args.margin_top,
+            args.margin_right,
+            args.margin_bottom,
+            args.margin_left,
+        ],
     );
-
-    println!("{}", html_total);
+    let options = RenderOptions {
+        font_size: args.font_size.unwrap_or(defaults.font_size),
+        zoom_factor: args.zoom_factor.unwrap_or(defaults.zoom_factor),
+        mono_font: args.mono_font.unwrap_or(defaults.mono_font),
+        h1_weight: args.h1_weight.unwrap_or(defaults.h1_weight),
+        h2_weight: args.h2_weight.unwrap_or(defaults.h2_weight),
+        assets: args.assets.unwrap_or(defaults.assets),
+        math: args.math.unwrap_or(defaults.math),
+        highlight: args.highlight.unwrap_or(defaults.highlight),
+        toc: args.toc,
+        marks: !args.no_marks,
+        tags: !args.no_tags,
+        strip_comments: !args.keep_comments,
+        html: args.html.unwrap_or(defaults.html),
+        html_allow_list: HtmlAllowList {
+            tags: match args.allow_tags {
+                Some(tags) => tags.into_iter().map(|t| t.to_lowercase()).collect(),
+                None => defaults.html_allow_list.tags,
+            },
+            attributes: match args.allow_attributes {
+                Some(attributes) => attributes.into_iter().map(|a| a.to_lowercase()).collect(),
+                None => defaults.html_allow_list.attributes,
+            },
+        },
+        cover: args.cover,
+        cover_template: args
+            .cover_template
+            .map(|path| std::path::absolute(&path).unwrap_or(path)),
+        show_properties: !args.no_properties,
+        page,
+        theme: args.theme.unwrap_or(defaults.theme),
+        header: RunningText {
+            left: args.header_left,
+            center: args.header_center,
+            right: args.header_right,
+        },
+        footer: RunningText {
+            left: args.footer_left,
+            center: args.footer_center,
+            right: args.footer_right,
+        },
+        header_footer_on_first_page: args.header_footer_on_first_page,
+        page_break_on_rule: args.page_break_on_rule,
+        page_break_before_h1: args.page_break_before_h1,
+        ..defaults
+    };
+
+    let file = std::fs::canonicalize(&args.file).unwrap_or(args.file);
+
+    let renderer = Renderer::for_file(&file, options)?;
+
+    let mut out: BufWriter<Box<dyn Write>> = match &args.output {
+        Some(path) => BufWriter::new(Box::new(File::create(path).map_err(|e| {
+            RenderError::new(&file, format!("Couldn't create {}: {e}", path.display()))
+        })?)),
+        None => BufWriter::new(Box::new(io::stdout().lock())),
+    };
+
+    let diagnostics = renderer
+        .write_file(&file, &mut out)
+        .and_then(|diagnostics| {
+            out.flush()
+                .map_err(|e| RenderError::new(&file, format!("Couldn't write HTML: {e}")))?;
+            Ok(diagnostics)
+        });
+    drop(out);
+
+    let diagnostics = diagnostics.inspect_err(|_| remove_output(&args.output))?;
+
+    for diagnostic in &diagnostics {
+        eprintln!("{diagnostic}\n");
+    }
+
+    let errors = diagnostics
+        .iter()
+        .filter(|d| d.severity == Severity::Error)
+        .count();
+    if errors > 0 {
+        remove_output(&args.output);
+        return Err(RenderError::new(
+            &file,
+            format!("couldn't render the note due to {errors} previous error(s)"),
+        ));
+    }
 
     Ok(())
 }
 
+fn remove_output(output: &Option<PathBuf>) {
+    if let Some(path) = output {
+        let _ = std::fs::remove_file(path);
+    }
+}
+
 #[derive(Parser, Debug)]
 struct CliArgs {
     file: PathBuf,
+    /// Write the HTML to this file instead of standard output
+    #[arg(long, short)]
+    output: Option<PathBuf>,
     #[arg(long)]
     font_size: Option<i32>,
     #[arg(long)]
@@ -155,23 +150,88 @@ struct CliArgs {
     h1_weight: Option<u32>,
     #[arg(long)]
     h2_weight: Option<u32>,
-}
-
-fn html_body_of_md(input: &String) -> String {
-    let ast = markdown::to_mdast(
-        input,
-        &markdown::ParseOptions {
-            constructs: markdown::Constructs {
-                frontmatter: true,
-                ..markdown::Constructs::gfm()
-            },
-            gfm_strikethrough_single_tilde: false,
-            math_text_single_dollar: true,
-            mdx_expression_parse: None,
-            mdx_esm_parse: None,
-        },
-    )
-    .unwrap();
-
-    return ast_to_html::ast_to_html(ast);
+    /// Inline KaTeX and fonts into the document, or load them from CDNs
+    #[arg(long, value_enum)]
+    assets: Option<AssetMode>,
+    /// Typeset math while rendering, or with KaTeX in the browser
+    #[arg(long, value_enum)]
+    math: Option<MathMode>,
+    /// Highlight code while rendering, or with Prism in the browser
+    #[arg(long, value_enum)]
+    highlight: Option<HighlightMode>,
+    /// Put a table of contents after the properties, if the note has no `[TOC]` marker
+    #[arg(long)]
+    toc: bool,
+    /// Leave `==text==` as it is, rather than highlighting it
+    #[arg(long)]
+    no_marks: bool,
+    /// Leave `#words` as they are, rather than making them tags
+    #[arg(long)]
+    no_tags: bool,
+    /// Keep `%% comments %%` in the document, as written
+    #[arg(long)]
+    keep_comments: bool,
+    /// Keep raw HTML as written, or sanitise it for notes you didn't write yourself
+    #[arg(long, value_enum)]
+    html: Option<HtmlMode>,
+    /// The tags sanitised HTML can keep, replacing the default list
+    #[arg(long, value_delimiter = ',')]
+    allow_tags: Option<Vec<String>>,
+    /// The attributes sanitised HTML can keep, replacing the default list
+    #[arg(long, value_delimiter = ',')]
+    allow_attributes: Option<Vec<String>>,
+    /// Start with a cover made from the note's title, author, course, instructor and date
+    #[arg(long, value_enum)]
+    cover: Option<CoverLayout>,
+    /// Make the cover from an HTML file with `{{key}}` placeholders for frontmatter values
+    #[arg(long)]
+    cover_template: Option<PathBuf>,
+    /// Leave the properties block out, as when a cover already shows them
+    #[arg(long)]
+    no_properties: bool,
+    /// Render in the theme's light or dark mode, or whichever the vault uses
+    #[arg(long, value_enum)]
+    theme: Option<ThemeVariant>,
+    /// A4, letter, legal, or a width and height like "6in 9in"
+    #[arg(long)]
+    page_size: Option<PageSize>,
+    /// Which way up the pages are. Sections in a `<div class="landscape">` or
+    /// `<div class="portrait">` get pages of their own turned that way, for wide tables.
+    #[arg(long, value_enum)]
+    orientation: Option<Orientation>,
+    /// The space around the text, as one to four lengths like CSS's `margin`
+    #[arg(long)]
+    margin: Option<Margins>,
+    /// The space above the text, over what `--margin` says. The other sides work the same.
+    #[arg(long)]
+    margin_top: Option<Length>,
+    #[arg(long)]
+    margin_right: Option<Length>,
+    #[arg(long)]
+    margin_bottom: Option<Length>,
+    #[arg(long)]
+    margin_left: Option<Length>,
+    /// Text for the left of each page's header. `{page}`, `{pages}`, `{date}`, `{hash}`
+    /// and frontmatter keys like `{title}` are filled in. The other slots work the same.
+    #[arg(long)]
+    header_left: Option<String>,
+    #[arg(long)]
+    header_center: Option<String>,
+    #[arg(long)]
+    header_right: Option<String>,
+    #[arg(long)]
+    footer_left: Option<String>,
+    #[arg(long)]
+    footer_center: Option<String>,
+    #[arg(long)]
+    footer_right: Option<String>,
+    /// Put the header and footer on the first page too
+    #[arg(long)]
+    header_footer_on_first_page: bool,
+    /// Start a new page at each `---` rule. `<!-- pagebreak -->` and `\newpage` always do.
+    #[arg(long)]
+    page_break_on_rule: bool,
+    /// Start each top-level heading on a new page
+    #[arg(long)]
+    page_break_before_h1: bool,
 }
@@ -0,0 +1,135 @@
+use clap::ValueEnum;
+use katex::{Opts, OutputType};
+use markdown::mdast::{InlineMath, Node, Text};
+use serde::Deserialize;
+
+/// How `$...$` and `$$...$$` math gets turned into something printable
+#[derive(ValueEnum, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum MathMode {
+    /// Typeset with KaTeX while rendering, so the page needs no JavaScript
+    Static,
+    /// Leave a script after each formula that typesets it when Chrome loads the page
+    Script,
+}
+
+/// What static math is typeset into
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MathOutput {
+    /// KaTeX's own HTML, which needs KaTeX's stylesheet and fonts, with MathML alongside
+    Html,
+    /// MathML only, which Chrome lays out by itself
+    Mathml,
+}
+
+/// Turn `$...$` that Obsidian wouldn't take for math back into the text it was written
+/// as. Obsidian needs the TeX to start and end right next to its dollar signs, which keeps
+/// prose like "I paid $5 and $10" from becoming a formula.
+pub fn revert_loose_inline_math(ast: &mut Node, source: &str) {
+    let Some(children) = ast.children_mut() else {
+        return;
+    };
+
+    let mut reverted = false;
+    for child in children.iter_mut() {
+        let Node::InlineMath(InlineMath {
+            position: Some(position),
+            ..
+        }) = child
+        else {
+            revert_loose_inline_math(child, source);
+            continue;
+        };
+
+        let written = &source[position.start.offset..position.end.offset];
+        let tex = written.trim_matches('$');
+        if tex.is_empty()
+            || tex.starts_with(char::is_whitespace)
+            || tex.ends_with(char::is_whitespace)
+        {
+            *child = Node::Text(Text {
+                value: written.to_string(),
+                position: Some(position.clone()),
+            });
+            reverted = true;
+        }
+    }
+
+    // Join the text back up, so wikilinks and highlights that were split by the math
+    // are found again
+    if reverted {
+        children.dedup_by(|next, previous| match (next, previous) {
+            (Node::Text(next), Node::Text(previous)) => {
+                previous.value += &next.value;
+                if let (Some(previous), Some(next)) = (&mut previous.position, &next.position) {
+                    previous.end = next.end.clone();
+                }
+                true
+            }
+            _ => false,
+        });
+    }
+}
+
+/// Typeset TeX into static markup, or explain why it couldn't be
+pub fn render_tex(tex: &str, display: bool, output: MathOutput) -> Result<String, String> {
+    let output_type = match output {
+        MathOutput::Html => OutputType::HtmlAndMathml,
+        MathOutput::Mathml => OutputType::Mathml,
+    };
+
+    let opts = Opts::builder()
+        .display_mode(display)
+        .output_type(output_type)
+        .throw_on_error(true)
+        .build()
+        .map_err(|e| e.to_string())?;
+
+    katex::render_with_opts(tex, &opts).map_err(tex_error_message)
+}
+
+/// KaTeX's errors come back wrapped in the JS engine's; dig out the part about the TeX
+fn tex_error_message(error: katex::Error) -> String {
+    let message = match error {
+        katex::Error::JsExecError(message) => message,
+        e => return e.to_string(),
+    };
+
+    let message = message
+        .trim_start_matches("String(")
+        .trim_end_matches(')')
+        .trim_matches('"')
+        .replace("\\\\", "\\");
+
+    match message.split_once("KaTeX parse error: ") {
+        Some((_, tex_error)) => tex_error.to_string(),
+        None => message,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::ast_to_html::parse_markdown;
+
+    #[test]
+    fn currency_stays_text() {
+        let ast = parse_markdown("I paid $5 and $10 today.").unwrap();
+        assert_eq!(ast.to_string(), "I paid $5 and $10 today.");
+        let paragraph = &ast.children().unwrap()[0];
+        assert_eq!(paragraph.children().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn math_next_to_its_dollars_is_kept() {
+        let ast = parse_markdown("Where $x^2$ and $ y $ differ").unwrap();
+        let paragraph = &ast.children().unwrap()[0];
+        let kinds = paragraph
+            .children()
+            .unwrap()
+            .iter()
+            .map(|node| matches!(node, markdown::mdast::Node::InlineMath(_)))
+            .collect::<Vec<_>>();
+        assert_eq!(kinds, [false, true, false]);
+        assert_eq!(ast.to_string(), "Where x^2 and $ y $ differ");
+    }
+}
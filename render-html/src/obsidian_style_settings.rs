@@ -13,15 +13,15 @@ pub struct StyleSettingsCss {
     pub body_classes: String,
 }
 
-const START_SIGIL: &'static str = "/* @settings";
-const END_SIGIL: &'static str = "*/";
+const START_SIGIL: &str = "/* @settings";
+const END_SIGIL: &str = "*/";
 
 pub fn get_style_settings_css(
     vault: &ObsidianVault,
     theme_css: String,
     theme_variant: &ObsidianTheme,
 ) -> Result<StyleSettingsCss, Error> {
-    let style_setting_values = style_settings_map(&vault, None, theme_variant)?.unwrap_or_default();
+    let style_setting_values = style_settings_map(vault, None, theme_variant)?.unwrap_or_default();
 
     let mut root_css = String::new();
 
@@ -46,16 +46,14 @@ pub fn get_style_settings_css(
                     style_setting_config.id.to_string(),
                     setting_config.id.to_string(),
                 ))
-                .map(|x| (*x).as_str())
+                .map(|x| x.as_str())
             else {
                 continue;
             };
 
             match setting_config.r#type {
-                "class-toggle" => {
-                    if value == "true" {
-                        body_classes.push(setting_config.id.to_string());
-                    }
+                "class-toggle" if value == "true" => {
+                    body_classes.push(setting_config.id.to_string());
                 }
                 "class-select" => {
                     body_classes.push(value.to_string());
@@ -66,7 +64,7 @@ pub fn get_style_settings_css(
                 | "variable-number" => {
                     root_css = add_variable_with_value(
                         root_css,
-                        &setting_config.id,
+                        setting_config.id,
                         value.to_string() + setting_config.format.unwrap_or_default(),
                     );
                 }
@@ -112,13 +110,12 @@ fn add_color_format(
             id,
             color.to_rgba8().map(|x| x.to_string()).join(" "),
         ),
-        Some("hsl-values") => match color.to_hsla() {
-            (h, s, l, a) => {
-                let s = s * 100.;
-                let l = l * 100.;
-                add_variable_with_value(root_css, id, format!("{h} {s}% {l}% {a}"))
-            }
-        },
+        Some("hsl-values") => {
+            let (h, s, l, a) = color.to_hsla();
+            let s = s * 100.;
+            let l = l * 100.;
+            add_variable_with_value(root_css, id, format!("{h} {s}% {l}% {a}"))
+        }
 
         Some("rgb-split") => {
             let (r, g, b, a) = color.to_linear_rgba_u8();
@@ -172,7 +169,7 @@ impl<'a> Iterator for Comments<'a> {
     fn next(&mut self) -> Option<Self::Item> {
         let Comments(chars, buf, buffering) = self;
 
-        while let Some(c) = chars.next() {
+        for c in chars.by_ref() {
             if *buffering == 0 && c == '/' {
                 *buffering = 1;
                 buf.clear();
@@ -192,15 +189,17 @@ impl<'a> Iterator for Comments<'a> {
     }
 }
 
-fn css_settings_comments(theme_css: &String) -> Comments<'_> {
+fn css_settings_comments(theme_css: &str) -> Comments<'_> {
     return Comments(theme_css.chars(), String::new(), 0);
 }
 
+type StyleSettingValues = HashMap<(String, String), String>;
+
 fn style_settings_map(
     vault: &ObsidianVault,
     category: Option<&str>,
     theme: &ObsidianTheme,
-) -> Result<Option<HashMap<(String, String), String>>, Error> {
+) -> Result<Option<StyleSettingValues>, Error> {
     let dir = &vault.0;
 
     let style_settings_file = dir.join("plugins/obsidian-style-settings/data.json");
@@ -1,11 +1,17 @@
-use std::{error::Error, path::PathBuf};
+use std::{
+    cell::OnceCell,
+    collections::HashMap,
+    error::Error,
+    path::{Path, PathBuf},
+};
 
 use anyhow::Context;
+use clap::ValueEnum;
 use serde::Deserialize;
 
 use crate::obsidian_style_settings::{get_style_settings_css, StyleSettingsCss};
 
-pub struct ObsidianVault(pub PathBuf);
+pub struct ObsidianVault(pub PathBuf, OnceCell<Vec<PathBuf>>);
 
 const DEFAULT_BODY_CLASSES: [&str; 15] = [
     "mod-linux",
@@ -25,7 +31,8 @@ const DEFAULT_BODY_CLASSES: [&str; 15] = [
     "is-focused",
 ];
 
-#[derive(PartialEq, Eq)]
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
 pub enum ObsidianTheme {
     Light,
     Dark,
@@ -40,19 +47,141 @@ impl ObsidianTheme {
     }
 }
 
+/// Which of the theme's light and dark modes to render in
+#[derive(ValueEnum, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum ThemeVariant {
+    Light,
+    Dark,
+    /// Whichever the vault's appearance settings use
+    Vault,
+}
+
+impl ThemeVariant {
+    pub fn resolve(self, vault: &ObsidianVault) -> ObsidianTheme {
+        match self {
+            ThemeVariant::Light => ObsidianTheme::Light,
+            ThemeVariant::Dark => ObsidianTheme::Dark,
+            ThemeVariant::Vault => vault.theme_variant(),
+        }
+    }
+}
+
 impl ObsidianVault {
-    pub fn vault_of_file(file: &PathBuf) -> Result<Option<ObsidianVault>, Box<dyn Error>> {
+    pub fn vault_of_file(file: &Path) -> Result<Option<ObsidianVault>, Box<dyn Error>> {
         for folder in file.ancestors().skip(1) {
             for subfile in folder.read_dir()? {
                 let subfile = subfile.with_context(|| "Reading directory entry of {folder}")?;
                 if subfile.file_name() == ".obsidian" && subfile.file_type()?.is_dir() {
-                    return Ok(Some(ObsidianVault(folder.join(subfile.file_name()))));
+                    return Ok(Some(ObsidianVault(
+                        folder.join(subfile.file_name()),
+                        OnceCell::new(),
+                    )));
                 }
             }
         }
         Ok(None)
     }
 
+    /// The folder containing `.obsidian`, which is where notes and attachments live
+    pub fn root(&self) -> &Path {
+        self.0.parent().unwrap_or(&self.0)
+    }
+
+    /// Whether a path is inside the vault once `..` and symlinks are followed, so that a
+    /// note can't pull in files from elsewhere on the machine
+    pub fn contains(&self, path: &Path) -> bool {
+        match (path.canonicalize(), self.root().canonicalize()) {
+            (Ok(path), Ok(root)) => path.starts_with(root),
+            _ => false,
+        }
+    }
+
+    /// Every file in the vault, skipping hidden folders like `.obsidian` and `.git`.
+    /// Walked once and then cached, since link resolution asks for it repeatedly.
+    pub fn files(&self) -> &Vec<PathBuf> {
+        self.1.get_or_init(|| {
+            let mut files = Vec::new();
+            collect_files(self.root(), &mut files);
+            files.sort_by_key(|f| (f.components().count(), f.clone()));
+            files
+        })
+    }
+
+    /// Resolve a wikilink target the way Obsidian does: relative to the linking note,
+    /// then relative to the vault root, then by the shortest matching path anywhere in
+    /// the vault. Targets without an extension are treated as Markdown notes.
+    pub fn resolve_link(&self, target: &str, from: &Path) -> Option<PathBuf> {
+        if target.is_empty() {
+            return Some(from.to_path_buf());
+        }
+
+        let mut candidates = vec![target.to_string()];
+        if Path::new(target).extension().is_none_or(|ext| ext != "md") {
+            candidates.insert(0, format!("{target}.md"));
+        }
+
+        for candidate in &candidates {
+            let siblings = from.parent().map(|dir| dir.join(candidate));
+            let from_root = self.root().join(candidate);
+
+            for path in siblings.into_iter().chain([from_root]) {
+                if path.is_file() && self.contains(&path) {
+                    return Some(path);
+                }
+            }
+        }
+
+        candidates
+            .iter()
+            .find_map(|candidate| self.find_by_suffix(candidate))
+    }
+
+    /// Resolve an image or other attachment the way Obsidian does: relative to the note
+    /// that uses it, then inside the vault's configured attachment folder, then anywhere
+    /// in the vault by filename.
+    pub fn resolve_attachment(&self, target: &str, from: &Path) -> Option<PathBuf> {
+        let note_folder = from.parent().unwrap_or(self.root());
+
+        let attachment_folder = match self.app_config() {
+            Ok(Ok(ObsidianAppConfig {
+                attachmentFolderPath: Some(folder),
+            })) => match folder.strip_prefix("./") {
+                Some(relative) => Some(note_folder.join(relative)),
+                None if folder == "." => Some(note_folder.to_path_buf()),
+                None => Some(self.root().join(folder.trim_start_matches('/'))),
+            },
+            _ => None,
+        };
+
+        let candidates = [Some(note_folder.to_path_buf()), attachment_folder]
+            .into_iter()
+            .flatten()
+            .map(|folder| folder.join(target));
+
+        for path in candidates {
+            if path.is_file() && self.contains(&path) {
+                return Some(path);
+            }
+        }
+
+        self.find_by_suffix(target)
+    }
+
+    /// The shortest path in the vault that ends with `suffix`, compared case-insensitively
+    fn find_by_suffix(&self, suffix: &str) -> Option<PathBuf> {
+        let suffix = suffix.to_lowercase();
+
+        self.files()
+            .iter()
+            .find(|file| {
+                let relative = file.strip_prefix(self.root()).unwrap_or(file);
+                let relative = relative.to_string_lossy().to_lowercase();
+                relative == suffix || relative.ends_with(&format!("/{suffix}"))
+            })
+            .cloned()
+    }
+
     pub fn appearance(
         &self,
     ) -> Result<Result<ObsidianAppearance, serde_json::Error>, std::io::Error> {
@@ -61,18 +190,83 @@ impl ObsidianVault {
         return Ok(serde_json::from_reader(file_content));
     }
 
-    pub fn style_css(
+    pub fn app_config(
         &self,
-        theme_variant: &ObsidianTheme,
-    ) -> Result<Option<StyleSettingsCss>, Box<dyn Error>> {
+    ) -> Result<Result<ObsidianAppConfig, serde_json::Error>, std::io::Error> {
+        let file_content = std::fs::File::open(self.0.join("app.json"))?;
+
+        return Ok(serde_json::from_reader(file_content));
+    }
+
+    /// The mode the vault's appearance settings use. Obsidian calls dark mode `obsidian`
+    /// and light mode `moonstone`, and there's no telling what `system` would be, so
+    /// anything but dark mode is light.
+    pub fn theme_variant(&self) -> ObsidianTheme {
+        std::fs::read_to_string(self.0.join("appearance.json"))
+            .map(|json| theme_of_appearance(&json))
+            .unwrap_or(ObsidianTheme::Light)
+    }
+
+    /// The CSS of the theme the vault uses, if it uses one
+    pub fn theme_css(&self) -> Result<Option<String>, Box<dyn Error>> {
         let appearance = self.appearance()??;
 
         let Some(theme) = appearance.cssTheme else {
             return Ok(None);
         };
 
-        let theme_css =
-            std::fs::read_to_string(self.0.join("themes").join(theme).join("theme.css"))?;
+        let css = std::fs::read_to_string(self.0.join("themes").join(theme).join("theme.css"))?;
+
+        return Ok(Some(css));
+    }
+
+    /// The CSS of each snippet enabled in the vault's appearance settings. Snippets that
+    /// can't be read are skipped.
+    pub fn enabled_snippets_css(&self) -> Vec<String> {
+        let Ok(Ok(appearance)) = self.appearance() else {
+            return Vec::new();
+        };
+
+        appearance
+            .enabledCssSnippets
+            .iter()
+            .filter_map(|name| {
+                std::fs::read_to_string(self.0.join("snippets").join(format!("{name}.css"))).ok()
+            })
+            .collect()
+    }
+
+    /// The type set for each property in the properties panel, like `date` or `tags`.
+    /// Empty if the vault has never had a property's type set.
+    pub fn property_types(&self) -> HashMap<String, String> {
+        #[derive(Deserialize)]
+        struct Types {
+            types: HashMap<String, String>,
+        }
+
+        let Ok(file) = std::fs::File::open(self.0.join("types.json")) else {
+            return HashMap::new();
+        };
+
+        serde_json::from_reader::<_, Types>(file)
+            .map(|t| t.types)
+            .unwrap_or_default()
+    }
+
+    /// A community plugin's saved settings, if it's installed and has any
+    pub fn plugin_data(&self, plugin_id: &str) -> Option<serde_json::Value> {
+        let file = std::fs::File::open(self.0.join("plugins").join(plugin_id).join("data.json"));
+
+        serde_json::from_reader(file.ok()?).ok()
+    }
+
+    pub fn style_css(
+        &self,
+        theme_variant: &ObsidianTheme,
+    ) -> Result<Option<StyleSettingsCss>, Box<dyn Error>> {
+        let Some(theme_css) = self.theme_css()? else {
+            return Ok(None);
+        };
 
         let mut style = get_style_settings_css(self, theme_css, theme_variant)?;
 
@@ -85,8 +279,43 @@ impl ObsidianVault {
     }
 }
 
-#[allow(non_snake_case)]
-#[derive(Deserialize)]
+fn collect_files(folder: &Path, files: &mut Vec<PathBuf>) {
+    let Ok(entries) = folder.read_dir() else {
+        return;
+    };
+
+    for entry in entries.flatten() {
+        if entry.file_name().to_string_lossy().starts_with('.') {
+            continue;
+        }
+
+        match entry.file_type() {
+            Ok(t) if t.is_dir() => collect_files(&entry.path(), files),
+            Ok(t) if t.is_file() => files.push(entry.path()),
+            _ => {}
+        }
+    }
+}
+
+/// The mode an `appearance.json` sets. Obsidian leaves settings still at their defaults out
+/// of the file, so this reads `theme` on its own rather than the whole of it.
+fn theme_of_appearance(json: &str) -> ObsidianTheme {
+    #[derive(Deserialize)]
+    struct Appearance {
+        theme: Option<String>,
+    }
+
+    match serde_json::from_str(json) {
+        Ok(Appearance { theme: Some(theme) }) if theme == "obsidian" => ObsidianTheme::Dark,
+        _ => ObsidianTheme::Light,
+    }
+}
+
+/// The vault's `appearance.json`. Obsidian leaves out settings still at their defaults,
+/// so every field has one.
+#[allow(non_snake_case, dead_code)]
+#[derive(Deserialize, Default)]
+#[serde(default)]
 pub struct ObsidianAppearance {
     pub baseFontSize: i32,
     pub theme: Option<String>,
@@ -94,4 +323,37 @@ pub struct ObsidianAppearance {
     pub accentColor: Option<String>,
     pub translucency: bool,
     pub monospaceFontFamily: String,
+    pub enabledCssSnippets: Vec<String>,
+}
+
+#[allow(non_snake_case)]
+#[derive(Deserialize)]
+pub struct ObsidianAppConfig {
+    pub attachmentFolderPath: Option<String>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_theme_from_sparse_appearance() {
+        assert_eq!(
+            theme_of_appearance(r#"{"theme":"obsidian"}"#),
+            ObsidianTheme::Dark
+        );
+        assert_eq!(
+            theme_of_appearance(r#"{"theme":"moonstone","cssTheme":"Minimal"}"#),
+            ObsidianTheme::Light
+        );
+        assert_eq!(theme_of_appearance("{}"), ObsidianTheme::Light);
+    }
+
+    #[test]
+    fn reads_sparse_appearance() {
+        let appearance: ObsidianAppearance =
+            serde_json::from_str(r#"{"theme":"obsidian","cssTheme":"Minimal"}"#).unwrap();
+        assert_eq!(appearance.cssTheme.as_deref(), Some("Minimal"));
+        assert!(appearance.enabledCssSnippets.is_empty());
+    }
 }
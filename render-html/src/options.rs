@@ -0,0 +1,264 @@
+use std::path::{Component, PathBuf};
+
+use serde::Deserialize;
+
+use crate::{
+    assets::{self, AssetMode},
+    cover::CoverLayout,
+    header_footer::RunningText,
+    highlight::HighlightMode,
+    math::{MathMode, MathOutput},
+    obsidian_vault::ThemeVariant,
+    page::{Length, Margins, Orientation, PageGeometry, PageSize},
+    sanitize::{HtmlAllowList, HtmlMode},
+};
+
+pub const DEFAULT_FONT_SIZE: i32 = 18;
+pub const DEFAULT_ZOOM_FACTOR: f64 = 1.; //0.9128709291752769;
+pub const DEFAULT_MONO_FONT: &str = "Fira Code Retina";
+pub const DEFAULT_H1_WEIGHT: u32 = 800;
+pub const DEFAULT_H2_WEIGHT: u32 = 800;
+pub const DEFAULT_MARGIN: &str = "0.65in";
+
+/// The frontmatter key a note can set its own render options under
+pub const SETTINGS_KEY: &str = "gh-canvas";
+
+/// Choices about how a note is turned into an HTML document
+#[derive(Clone, Debug)]
+pub struct RenderOptions {
+    /// Body text size, in pixels
+    pub font_size: i32,
+    pub zoom_factor: f64,
+    pub mono_font: String,
+    pub h1_weight: u32,
+    pub h2_weight: u32,
+    /// Where KaTeX and the fonts come from. Inlined by default, unless this build is
+    /// missing the fonts and stylesheets that `fetch-assets.sh` downloads.
+    pub assets: AssetMode,
+    pub math: MathMode,
+    pub highlight: HighlightMode,
+    /// Whether to add a table of contents even when the note doesn't ask for one
+    pub toc: bool,
+    /// Turn `==text==` into highlighted `<mark>`s
+    pub marks: bool,
+    /// Turn `#tag`s into tag pills
+    pub tags: bool,
+    /// Leave `%% comments %%` out of the document
+    pub strip_comments: bool,
+    /// Whether raw HTML is kept as written or sanitised
+    pub html: HtmlMode,
+    /// The tags and attributes sanitised HTML can keep
+    pub html_allow_list: HtmlAllowList,
+    /// The size, orientation and margins of the pages
+    pub page: PageGeometry,
+    /// Light or dark mode, or whichever the vault uses
+    pub theme: ThemeVariant,
+    /// Whether a note's `gh-canvas:` settings show up in its properties block
+    pub show_settings: bool,
+    /// Start the document with a cover made from the frontmatter
+    pub cover: Option<CoverLayout>,
+    /// An HTML file to make the cover from instead, with `{{key}}` placeholders for
+    /// frontmatter values. Relative paths are relative to the vault.
+    pub cover_template: Option<PathBuf>,
+    /// Whether the frontmatter is shown as a properties block, which a cover can make
+    /// unnecessary
+    pub show_properties: bool,
+    /// Text along the top of each page
+    pub header: RunningText,
+    /// Text along the bottom of each page
+    pub footer: RunningText,
+    /// Whether the first page gets the header and footer too
+    pub header_footer_on_first_page: bool,
+    /// Start a new page at each `---` rule, rather than drawing a line
+    pub page_break_on_rule: bool,
+    /// Start each top-level heading on a new page
+    pub page_break_before_h1: bool,
+}
+
+impl Default for RenderOptions {
+    fn default() -> Self {
+        RenderOptions {
+            font_size: DEFAULT_FONT_SIZE,
+            zoom_factor: DEFAULT_ZOOM_FACTOR,
+            mono_font: DEFAULT_MONO_FONT.to_string(),
+            h1_weight: DEFAULT_H1_WEIGHT,
+            h2_weight: DEFAULT_H2_WEIGHT,
+            assets: AssetMode::preferred(),
+            math: MathMode::Static,
+            highlight: HighlightMode::Static,
+            toc: false,
+            marks: true,
+            tags: true,
+            strip_comments: true,
+            html: HtmlMode::Keep,
+            html_allow_list: HtmlAllowList::default(),
+            page: PageGeometry::default(),
+            theme: ThemeVariant::Light,
+            show_settings: true,
+            cover: None,
+            cover_template: None,
+            show_properties: true,
+            header: RunningText::default(),
+            footer: RunningText::default(),
+            header_footer_on_first_page: false,
+            page_break_on_rule: false,
+            page_break_before_h1: false,
+        }
+    }
+}
+
+impl RenderOptions {
+    /// What static math is typeset into, which depends on KaTeX's stylesheet being there
+    pub fn math_output(&self) -> MathOutput {
+        if assets::has_katex_stylesheet(self.assets) {
+            MathOutput::Html
+        } else {
+            MathOutput::Mathml
+        }
+    }
+}
+
+/// Render options a note sets for itself with a `gh-canvas:` mapping in its frontmatter.
+/// The keys are the same as the command line flags.
+#[derive(Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+struct NoteSettings {
+    font_size: Option<i32>,
+    zoom_factor: Option<f64>,
+    mono_font: Option<String>,
+    h1_weight: Option<u32>,
+    h2_weight: Option<u32>,
+    assets: Option<AssetMode>,
+    math: Option<MathMode>,
+    highlight: Option<HighlightMode>,
+    toc: Option<bool>,
+    marks: Option<bool>,
+    tags: Option<bool>,
+    strip_comments: Option<bool>,
+    html: Option<HtmlMode>,
+    page_size: Option<PageSize>,
+    orientation: Option<Orientation>,
+    margin: Option<Margins>,
+    margin_top: Option<Length>,
+    margin_right: Option<Length>,
+    margin_bottom: Option<Length>,
+    margin_left: Option<Length>,
+    theme: Option<ThemeVariant>,
+    cover: Option<CoverLayout>,
+    cover_template: Option<PathBuf>,
+    properties: Option<bool>,
+    header: Option<RunningText>,
+    footer: Option<RunningText>,
+    header_footer_on_first_page: Option<bool>,
+    page_break_on_rule: Option<bool>,
+    page_break_before_h1: Option<bool>,
+    /// Leave the settings out of the properties block
+    hide: Option<bool>,
+}
+
+impl RenderOptions {
+    /// These options with a note's `gh-canvas:` settings applied over them, along with
+    /// warnings about any settings that were ignored. Fails if the settings aren't valid,
+    /// in which case none of them apply.
+    pub fn with_note_settings(
+        &self,
+        settings: serde_yaml::Value,
+    ) -> Result<(RenderOptions, Vec<String>), String> {
+        let settings: NoteSettings = serde_yaml::from_value(settings)
+            .map_err(|e| format!("invalid {SETTINGS_KEY} settings: {e}"))?;
+        if settings
+            .mono_font
+            .as_deref()
+            .is_some_and(|v| !is_plain_css_value(v))
+        {
+            return Err(format!("invalid {SETTINGS_KEY} mono-font: not a CSS value"));
+        }
+
+        // A note can only use a template from inside its own vault
+        if let Some(template) = &settings.cover_template {
+            let outside_vault = template
+                .components()
+                .any(|c| !matches!(c, Component::Normal(_) | Component::CurDir));
+            if outside_vault {
+                return Err(format!(
+                    "invalid {SETTINGS_KEY} cover-template: must be a path inside the vault"
+                ));
+            }
+        }
+
+        let mut options = self.clone();
+        let mut warnings = Vec::new();
+
+        macro_rules! apply {
+            ($($field:ident),*) => {
+                $(if let Some(value) = settings.$field {
+                    options.$field = value;
+                })*
+            };
+        }
+        apply!(
+            font_size,
+            zoom_factor,
+            mono_font,
+            h1_weight,
+            h2_weight,
+            assets,
+            math,
+            highlight,
+            toc,
+            marks,
+            tags,
+            strip_comments,
+            theme,
+            header_footer_on_first_page,
+            page_break_on_rule,
+            page_break_before_h1
+        );
+        options.page.apply(
+            settings.page_size,
+            settings.orientation,
+            settings.margin,
+            [
+                settings.margin_top,
+                settings.margin_right,
+                settings.margin_bottom,
+                settings.margin_left,
+            ],
+        );
+        if settings.cover.is_some() {
+            options.cover = settings.cover;
+        }
+        if settings.cover_template.is_some() {
+            options.cover_template = settings.cover_template;
+        }
+        if let Some(properties) = settings.properties {
+            options.show_properties = properties;
+        }
+        if let Some(header) = settings.header {
+            options.header.merge(header);
+        }
+        if let Some(footer) = settings.footer {
+            options.footer.merge(footer);
+        }
+
+        // A note we're sanitising mustn't be able to turn sanitising off
+        match settings.html {
+            Some(HtmlMode::Keep) if self.html == HtmlMode::Sanitize => warnings.push(format!(
+                "ignoring {SETTINGS_KEY} html: keep, since raw HTML is being sanitised"
+            )),
+            Some(html) => options.html = html,
+            None => {}
+        }
+        if let Some(hide) = settings.hide {
+            options.show_settings = !hide;
+        }
+
+        Ok((options, warnings))
+    }
+}
+
+/// Whether a value can go into the document's CSS as it is, without closing the rule or
+/// the `<style>` element it's in
+fn is_plain_css_value(value: &str) -> bool {
+    !value.contains(['<', '>', '{', '}', ';', '"', '\\'])
+}
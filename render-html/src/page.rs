@@ -0,0 +1,238 @@
+use std::{fmt, str::FromStr, sync::OnceLock};
+
+use clap::ValueEnum;
+use markdown::mdast::{Html, Node, Paragraph, Text};
+use regex::Regex;
+use serde::Deserialize;
+
+use crate::options::DEFAULT_MARGIN;
+
+/// The paper a note is printed on
+#[derive(Deserialize, Clone, Debug, PartialEq, Eq)]
+#[serde(try_from = "String")]
+pub enum PageSize {
+    A4,
+    Letter,
+    Legal,
+    /// A width and height, as they'd be for a portrait page
+    Custom {
+        width: Length,
+        height: Length,
+    },
+}
+
+/// Which way up the pages are
+#[derive(ValueEnum, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum Orientation {
+    Portrait,
+    Landscape,
+}
+
+/// A CSS length in a unit that means something on paper, like `0.65in` or `2cm`
+#[derive(Deserialize, Clone, Debug, PartialEq, Eq)]
+#[serde(try_from = "String")]
+pub struct Length(String);
+
+/// The space between each edge of the page and the text
+#[derive(Deserialize, Clone, Debug, PartialEq, Eq)]
+#[serde(try_from = "String")]
+pub struct Margins {
+    pub top: Length,
+    pub right: Length,
+    pub bottom: Length,
+    pub left: Length,
+}
+
+/// The size, orientation and margins of every page, which the `@page` rules and the
+/// spacing around the content are both made from
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PageGeometry {
+    pub size: PageSize,
+    pub orientation: Orientation,
+    pub margins: Margins,
+}
+
+impl Default for PageGeometry {
+    fn default() -> Self {
+        PageGeometry {
+            size: PageSize::Letter,
+            orientation: Orientation::Portrait,
+            margins: Margins::all(Length(DEFAULT_MARGIN.to_string())),
+        }
+    }
+}
+
+impl PageGeometry {
+    /// Set whatever's given, with the margins for single sides going over `margins`
+    pub fn apply(
+        &mut self,
+        size: Option<PageSize>,
+        orientation: Option<Orientation>,
+        margins: Option<Margins>,
+        [top, right, bottom, left]: [Option<Length>; 4],
+    ) {
+        if let Some(size) = size {
+            self.size = size;
+        }
+        if let Some(orientation) = orientation {
+            self.orientation = orientation;
+        }
+        if let Some(margins) = margins {
+            self.margins = margins;
+        }
+
+        let margins = &mut self.margins;
+        for (side, length) in [
+            (&mut margins.top, top),
+            (&mut margins.right, right),
+            (&mut margins.bottom, bottom),
+            (&mut margins.left, left),
+        ] {
+            if let Some(length) = length {
+                *side = length;
+            }
+        }
+    }
+
+    /// The CSS `size` of a page in the given orientation
+    pub(crate) fn css_size(&self, orientation: Orientation) -> String {
+        let name = match &self.size {
+            PageSize::A4 => "A4",
+            PageSize::Letter => "letter",
+            PageSize::Legal => "legal",
+            PageSize::Custom { width, height } => {
+                return match orientation {
+                    Orientation::Portrait => format!("{width} {height}"),
+                    Orientation::Landscape => format!("{height} {width}"),
+                };
+            }
+        };
+
+        match orientation {
+            Orientation::Portrait => format!("{name} portrait"),
+            Orientation::Landscape => format!("{name} landscape"),
+        }
+    }
+}
+
+impl Margins {
+    pub fn all(length: Length) -> Margins {
+        Margins {
+            top: length.clone(),
+            right: length.clone(),
+            bottom: length.clone(),
+            left: length,
+        }
+    }
+}
+
+impl FromStr for PageSize {
+    type Err = String;
+
+    /// `A4`, `letter` or `legal`, or a width and height like `6in 9in`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "a4" => return Ok(PageSize::A4),
+            "letter" => return Ok(PageSize::Letter),
+            "legal" => return Ok(PageSize::Legal),
+            _ => {}
+        }
+
+        match s.split_whitespace().collect::<Vec<_>>()[..] {
+            [width, height] => Ok(PageSize::Custom {
+                width: width.parse()?,
+                height: height.parse()?,
+            }),
+            _ => Err(format!(
+                "{s:?} isn't a page size: use A4, letter, legal, or a width and height like \"6in 9in\""
+            )),
+        }
+    }
+}
+
+impl FromStr for Length {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        static LENGTH: OnceLock<Regex> = OnceLock::new();
+        let length = LENGTH.get_or_init(|| {
+            Regex::new(r"^(?:0|\d+(?:\.\d+)?(?:in|cm|mm|pt|pc|px)|\.\d+(?:in|cm|mm|pt|pc|px))$")
+                .expect("length regex is valid")
+        });
+
+        match length.is_match(s.trim()) {
+            true => Ok(Length(s.trim().to_string())),
+            false => Err(format!(
+                "{s:?} isn't a length: use a number with a unit, like 0.65in, 2cm or 12pt"
+            )),
+        }
+    }
+}
+
+impl FromStr for Margins {
+    type Err = String;
+
+    /// One to four lengths, in the same order as CSS's `margin`: top, right, bottom, left
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let lengths = s
+            .split_whitespace()
+            .map(str::parse)
+            .collect::<Result<Vec<Length>, _>>()?;
+
+        let [top, right, bottom, left] = match &lengths[..] {
+            [all] => [all, all, all, all],
+            [vertical, horizontal] => [vertical, horizontal, vertical, horizontal],
+            [top, horizontal, bottom] => [top, horizontal, bottom, horizontal],
+            [top, right, bottom, left] => [top, right, bottom, left],
+            _ => return Err(format!("{s:?} isn't a margin: use one to four lengths")),
+        };
+
+        Ok(Margins {
+            top: top.clone(),
+            right: right.clone(),
+            bottom: bottom.clone(),
+            left: left.clone(),
+        })
+    }
+}
+
+/// Whether a node asks for a new page in its place: a `<!-- pagebreak -->` comment, a
+/// `\newpage` line like LaTeX's, or a `---` rule if `rules` says they're page breaks too
+pub fn page_break_marker(node: &Node, rules: bool) -> bool {
+    match node {
+        Node::Html(Html { value, .. }) => value
+            .trim()
+            .strip_prefix("<!--")
+            .and_then(|comment| comment.strip_suffix("-->"))
+            .is_some_and(|comment| {
+                let comment = comment.trim().to_lowercase();
+                comment == "pagebreak" || comment == "page-break" || comment == "page break"
+            }),
+        Node::Paragraph(Paragraph { children, .. }) => matches!(
+            children.as_slice(),
+            [Node::Text(Text { value, .. })] if value.trim() == "\\newpage"
+        ),
+        Node::ThematicBreak(_) => rules,
+        _ => false,
+    }
+}
+
+macro_rules! try_from_string {
+    ($($ty:ty),*) => {
+        $(impl TryFrom<String> for $ty {
+            type Error = String;
+
+            fn try_from(s: String) -> Result<Self, Self::Error> {
+                s.parse()
+            }
+        })*
+    };
+}
+try_from_string!(PageSize, Length, Margins);
+
+impl fmt::Display for Length {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
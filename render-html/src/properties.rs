@@ -0,0 +1,378 @@
+use std::sync::OnceLock;
+
+use markdown::unist::Position;
+use regex::Regex;
+use serde_yaml::Value;
+
+use crate::{
+    ast_to_html::{escape_html_str, wikilink_html, Definitions},
+    options::SETTINGS_KEY,
+    wikilink::{split_wikilinks, TextSegment},
+};
+
+/// The types a property can be given in Obsidian's properties panel
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PropertyType {
+    Text,
+    Multitext,
+    Number,
+    Checkbox,
+    Date,
+    Datetime,
+    Aliases,
+    Tags,
+}
+
+impl PropertyType {
+    fn parse(name: &str) -> Option<PropertyType> {
+        match name {
+            "text" => Some(PropertyType::Text),
+            "multitext" => Some(PropertyType::Multitext),
+            "number" => Some(PropertyType::Number),
+            "checkbox" => Some(PropertyType::Checkbox),
+            "date" => Some(PropertyType::Date),
+            "datetime" => Some(PropertyType::Datetime),
+            "aliases" => Some(PropertyType::Aliases),
+            "tags" => Some(PropertyType::Tags),
+            _ => None,
+        }
+    }
+
+    /// The type Obsidian would give a property that `types.json` doesn't mention, going by
+    /// its name and value. Mappings don't fit any type.
+    fn infer(key: &str, value: &Value) -> Option<PropertyType> {
+        match key {
+            "tags" => return Some(PropertyType::Tags),
+            "aliases" => return Some(PropertyType::Aliases),
+            "cssclasses" => return Some(PropertyType::Multitext),
+            _ => {}
+        }
+
+        match value {
+            Value::Bool(_) => Some(PropertyType::Checkbox),
+            Value::Number(_) => Some(PropertyType::Number),
+            Value::Sequence(_) => Some(PropertyType::Multitext),
+            Value::String(s) if parse_date(s).is_some_and(|d| d.time.is_some()) => {
+                Some(PropertyType::Datetime)
+            }
+            Value::String(s) if parse_date(s).is_some() => Some(PropertyType::Date),
+            Value::String(_) | Value::Null => Some(PropertyType::Text),
+            Value::Mapping(_) | Value::Tagged(_) => None,
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            PropertyType::Text => "text",
+            PropertyType::Multitext => "multitext",
+            PropertyType::Number => "number",
+            PropertyType::Checkbox => "checkbox",
+            PropertyType::Date => "date",
+            PropertyType::Datetime => "datetime",
+            PropertyType::Aliases => "aliases",
+            PropertyType::Tags => "tags",
+        }
+    }
+
+    /// The Lucide icon the properties panel shows next to a property of this type
+    fn icon_classname(self) -> &'static str {
+        match self {
+            PropertyType::Text => "icon-text",
+            PropertyType::Multitext => "icon-list",
+            PropertyType::Number => "icon-binary",
+            PropertyType::Checkbox => "icon-check-square",
+            PropertyType::Date => "icon-calendar",
+            PropertyType::Datetime => "icon-clock",
+            PropertyType::Aliases => "icon-forward",
+            PropertyType::Tags => "icon-tags",
+        }
+    }
+}
+
+/// The properties block for a note's frontmatter, with each property shown the way its
+/// type is shown in Obsidian's properties panel. Frontmatter that isn't valid YAML is
+/// shown as it was written.
+pub(crate) fn add_pretty_yaml(
+    value: String,
+    position: Option<Position>,
+    string: &mut String,
+    definitions: &mut Definitions,
+) {
+    let Ok(yaml) = serde_yaml::from_str::<Value>(&value) else {
+        *string += "<pre>";
+        *string += &escape_html_str(&value);
+        *string += "</pre>";
+        return;
+    };
+
+    let Value::Mapping(mut properties) = yaml else {
+        *string += r#"<div class="properties">"#;
+        add_pretty_yaml_parsed(yaml, string);
+        *string += r#"</div>"#;
+        return;
+    };
+
+    if !definitions.options().show_settings {
+        properties.remove(SETTINGS_KEY);
+    }
+    if properties.is_empty() {
+        return;
+    }
+
+    let types = definitions.vault().property_types();
+
+    *string += r#"<div class="properties"><dl>"#;
+    for (key, value) in properties {
+        let key = match key {
+            Value::String(key) => key,
+            key => scalar_text(&key),
+        };
+        let property_type = types
+            .get(&key)
+            .and_then(|name| PropertyType::parse(name))
+            .or_else(|| PropertyType::infer(&key, &value));
+
+        let Some(property_type) = property_type else {
+            *string += &format!("<dt>{}</dt><dd>", escape_html_str(&key));
+            add_pretty_yaml_parsed(value, string);
+            *string += "</dd>";
+            continue;
+        };
+
+        let type_name = property_type.name();
+        *string += &format!(
+            r#"<dt data-property-type="{type_name}"><i class="property-icon {}"></i>{}</dt>"#,
+            property_type.icon_classname(),
+            escape_html_str(&key)
+        );
+        *string += &format!(r#"<dd data-property-type="{type_name}">"#);
+        property_value_html(property_type, value, position.as_ref(), string, definitions);
+        *string += "</dd>";
+    }
+    *string += "</dl></div>";
+}
+
+fn property_value_html(
+    property_type: PropertyType,
+    value: Value,
+    position: Option<&Position>,
+    string: &mut String,
+    definitions: &mut Definitions,
+) {
+    match (property_type, value) {
+        (_, Value::Null) => {}
+        (PropertyType::Checkbox, Value::Bool(checked)) => {
+            *string += if checked {
+                r#"<input type="checkbox" checked disabled>"#
+            } else {
+                r#"<input type="checkbox" disabled>"#
+            };
+        }
+        (PropertyType::Date | PropertyType::Datetime, Value::String(s)) => {
+            let with_time = property_type == PropertyType::Datetime;
+            match parse_date(&s) {
+                Some(date) => *string += &date.html(&s, with_time),
+                None => *string += &escape_html_str(&s),
+            }
+        }
+        (PropertyType::Tags, value) => {
+            *string += r#"<div class="property-pills">"#;
+            for tag in list_items(value) {
+                let tag = scalar_text(&tag);
+                let tag = escape_html_str(tag.trim_start_matches('#'));
+                *string += &format!(r##"<a href="#{tag}" class="tag">{tag}</a>"##);
+            }
+            *string += "</div>";
+        }
+        (PropertyType::Multitext | PropertyType::Aliases, value) => {
+            *string += r#"<div class="property-pills">"#;
+            for item in list_items(value) {
+                *string += r#"<span class="property-pill">"#;
+                text_html(&scalar_text(&item), position, string, definitions);
+                *string += "</span>";
+            }
+            *string += "</div>";
+        }
+        (_, value @ (Value::Sequence(_) | Value::Mapping(_) | Value::Tagged(_))) => {
+            add_pretty_yaml_parsed(value, string);
+        }
+        (_, value) => text_html(&scalar_text(&value), position, string, definitions),
+    }
+}
+
+/// Text with any wikilinks in it resolved to links, like the properties panel shows them
+fn text_html(
+    text: &str,
+    position: Option<&Position>,
+    string: &mut String,
+    definitions: &mut Definitions,
+) {
+    for segment in split_wikilinks(text) {
+        match segment {
+            TextSegment::Text(text) => *string += &escape_html_str(text),
+            TextSegment::Wikilink(link) => {
+                *string += &wikilink_html(&link, position.cloned(), definitions)
+            }
+        }
+    }
+}
+
+/// The values of a list property, which can also be written as a single value
+fn list_items(value: Value) -> Vec<Value> {
+    match value {
+        Value::Sequence(items) => items,
+        Value::Null => Vec::new(),
+        value => vec![value],
+    }
+}
+
+pub(crate) fn scalar_text(value: &Value) -> String {
+    match value {
+        Value::String(s) => s.clone(),
+        Value::Number(n) => n.to_string(),
+        Value::Bool(b) => b.to_string(),
+        Value::Null => String::new(),
+        value => serde_yaml::to_string(value).unwrap_or_default(),
+    }
+}
+
+/// A date property written out in words, or `None` if it isn't a date
+pub(crate) fn long_date(s: &str, day_first: bool) -> Option<String> {
+    parse_date(s).map(|date| date.text(day_first))
+}
+
+struct Date {
+    year: u32,
+    month: usize,
+    day: u32,
+    time: Option<(u32, u32)>,
+}
+
+const MONTHS: [&str; 12] = [
+    "January",
+    "February",
+    "March",
+    "April",
+    "May",
+    "June",
+    "July",
+    "August",
+    "September",
+    "October",
+    "November",
+    "December",
+];
+
+/// A `2024-01-31` date or `2024-01-31T09:30` datetime, as Obsidian stores them
+fn parse_date(s: &str) -> Option<Date> {
+    static DATE: OnceLock<Regex> = OnceLock::new();
+    let date = DATE.get_or_init(|| {
+        Regex::new(r"^(\d{4})-(\d{2})-(\d{2})(?:[T ](\d{2}):(\d{2})(?::\d{2}(?:\.\d+)?)?)?$")
+            .expect("date regex is valid")
+    });
+
+    let captures = date.captures(s.trim())?;
+    let number = |i| captures.get(i).and_then(|m| m.as_str().parse::<u32>().ok());
+
+    let year = number(1)?;
+    let month = number(2)?;
+    let day = number(3)?;
+    if !(1..=12).contains(&month) || !(1..=days_in_month(year, month)).contains(&day) {
+        return None;
+    }
+
+    let time = match (number(4), number(5)) {
+        (Some(hour), Some(minute)) if hour < 24 && minute < 60 => Some((hour, minute)),
+        (Some(_), Some(_)) => return None,
+        _ => None,
+    };
+
+    Some(Date {
+        year,
+        month: month as usize,
+        day,
+        time,
+    })
+}
+
+fn days_in_month(year: u32, month: u32) -> u32 {
+    let leap_year =
+        year.is_multiple_of(4) && (!year.is_multiple_of(100) || year.is_multiple_of(400));
+
+    match month {
+        2 if leap_year => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+impl Date {
+    /// The date in words, `January 31, 2024`, or `31 January 2024` with `day_first`
+    fn text(&self, day_first: bool) -> String {
+        let month = MONTHS[self.month - 1];
+        match day_first {
+            true => format!("{} {month} {}", self.day, self.year),
+            false => format!("{month} {}, {}", self.day, self.year),
+        }
+    }
+
+    fn html(&self, source: &str, with_time: bool) -> String {
+        let mut text = self.text(false);
+        if let (true, Some((hour, minute))) = (with_time, self.time) {
+            text += &format!(" {hour:02}:{minute:02}");
+        }
+
+        format!(
+            r#"<time datetime="{}">{text}</time>"#,
+            escape_html_str(source.trim())
+        )
+    }
+}
+
+/// YAML that doesn't fit any property type, shown for what it is
+fn add_pretty_yaml_parsed(value: Value, string: &mut String) {
+    match value {
+        Value::Null => {}
+        Value::Bool(b) => {
+            *string += if b {
+                r#"<input type="checkbox" checked>"#
+            } else {
+                r#"<input type="checkbox">"#
+            };
+        }
+        Value::Number(n) => {
+            *string += &format!("<code>{n}</code>");
+        }
+        Value::String(s) => {
+            *string += &escape_html_str(&s);
+        }
+        Value::Sequence(list) => {
+            *string += "<ul>";
+            for itm in list {
+                *string += "<li>";
+                add_pretty_yaml_parsed(itm, string);
+                *string += "</li>";
+            }
+            *string += "</ul>";
+        }
+        Value::Mapping(map) => {
+            *string += "<dl>";
+            for (k, v) in map {
+                *string += "<dt>";
+                add_pretty_yaml_parsed(k, string);
+                *string += "</dt><dd>";
+                add_pretty_yaml_parsed(v, string);
+                *string += "</dd>";
+            }
+            *string += "</dl>";
+        }
+        Value::Tagged(tv) => {
+            *string += "<strong>";
+            *string += &tv.tag.to_string();
+            *string += "</strong>";
+
+            add_pretty_yaml_parsed(tv.value, string);
+        }
+    }
+}
@@ -0,0 +1,573 @@
+use std::{
+    io::{self, Write},
+    path::{Path, PathBuf},
+};
+
+use markdown::mdast::Node;
+use serde_yaml::Value;
+
+use crate::{
+    assets,
+    ast_to_html::{self, escape_html_str},
+    diagnostics::{Diagnostic, RenderError, Severity},
+    handler::{DefaultHandler, NodeHandler},
+    header_footer::header_footer_css,
+    highlight::{HighlightMode, PRISM_JS},
+    math::MathMode,
+    obsidian_style_settings::StyleSettingsCss,
+    obsidian_vault::{ObsidianTheme, ObsidianVault},
+    options::{RenderOptions, SETTINGS_KEY},
+    page::{Margins, Orientation},
+};
+
+const APP_CSS: &str = include_str!("./asset/app.css");
+const PROPERTIES_CSS: &str = include_str!("./asset/properties.css");
+
+/// Renders notes from one vault into print-ready HTML documents
+pub struct Renderer {
+    vault: ObsidianVault,
+    options: RenderOptions,
+    handler: Box<dyn NodeHandler>,
+}
+
+/// A rendered note, with its pieces kept apart so they can go into a page of your own
+/// as well as the standalone document `to_html` makes
+pub struct RenderedDocument {
+    /// The note itself, properties and footnotes included
+    pub body: String,
+    pub css: DocumentCss,
+    /// `<head>` markup that loads KaTeX, the fonts, and Prism if it's needed
+    pub head_assets: String,
+    /// Classes the theme expects on `<body>`
+    pub body_classes: String,
+    /// Inline style for `<body>`, setting the font size, zoom and monospace font
+    pub body_style: String,
+    /// Problems found while rendering. The body is still rendered when there are errors,
+    /// but parts of it will be missing.
+    pub diagnostics: Vec<Diagnostic>,
+}
+
+/// The stylesheets a rendered note needs, in the order they go in
+pub struct DocumentCss {
+    /// Obsidian's own stylesheet
+    pub app: &'static str,
+    /// The vault's theme, if it has one
+    pub theme: String,
+    /// Variables set through the Style Settings plugin
+    pub style_overrides: String,
+    /// Styling for the properties block
+    pub properties: &'static str,
+    /// Adjustments for printing, like page size and margins
+    pub print: String,
+}
+
+impl Renderer {
+    pub fn new(vault: ObsidianVault, options: RenderOptions) -> Self {
+        Renderer {
+            vault,
+            options,
+            handler: Box::new(DefaultHandler),
+        }
+    }
+
+    /// Render nodes with a handler of your own, for output that differs from the usual
+    pub fn with_handler(mut self, handler: impl NodeHandler + 'static) -> Self {
+        self.handler = Box::new(handler);
+        self
+    }
+
+    /// A renderer for the vault a note is in
+    pub fn for_file(file: &Path, options: RenderOptions) -> Result<Self, RenderError> {
+        let vault = ObsidianVault::vault_of_file(file)
+            .map_err(|e| RenderError::new(file, format!("Couldn't look for a vault: {e}")))?
+            .ok_or_else(|| RenderError::new(file, "Couldn't find Obsidian vault folder"))?;
+
+        Ok(Renderer::new(vault, options))
+    }
+
+    pub fn vault(&self) -> &ObsidianVault {
+        &self.vault
+    }
+
+    pub fn options(&self) -> &RenderOptions {
+        &self.options
+    }
+
+    /// Render Markdown that isn't saved in the vault. Links and embeds resolve as if it
+    /// were a note at the vault's root.
+    pub fn render_markdown(&self, markdown: &str) -> RenderedDocument {
+        let file = self.vault.root().join("Untitled.md");
+        self.render_note(markdown, &file)
+    }
+
+    /// Render a note read from a file in the vault
+    pub fn render_file(&self, file: &Path) -> Result<RenderedDocument, RenderError> {
+        let (markdown, file) = read_note(file)?;
+
+        Ok(self.render_note(&markdown, &file))
+    }
+
+    /// Render a note read from a file in the vault straight into `out`, as a standalone
+    /// HTML document. Returns the problems found while rendering.
+    pub fn write_file(
+        &self,
+        file: &Path,
+        out: &mut dyn Write,
+    ) -> Result<Vec<Diagnostic>, RenderError> {
+        let (markdown, file) = read_note(file)?;
+
+        self.write_note(&markdown, &file, out)
+            .map_err(|e| RenderError::new(&file, format!("Couldn't write HTML: {e}")))
+    }
+
+    /// Render a note's Markdown, with links and embeds resolved relative to `file`
+    pub fn render_note(&self, markdown: &str, file: &Path) -> RenderedDocument {
+        let note = self.parse_note(markdown, file);
+        let mut document = self.document(file, &note);
+
+        let mut body = Vec::new();
+        let diagnostics = self
+            .write_body(note, file, &mut body)
+            .expect("writing to a Vec can't fail");
+
+        document.body = String::from_utf8_lossy(&body).into_owned();
+        document.diagnostics.splice(0..0, diagnostics);
+        document
+    }
+
+    /// Render a note's Markdown straight into `out`, as a standalone HTML document, so
+    /// that the whole document never has to be held in memory at once
+    pub fn write_note(
+        &self,
+        markdown: &str,
+        file: &Path,
+        out: &mut dyn Write,
+    ) -> io::Result<Vec<Diagnostic>> {
+        let note = self.parse_note(markdown, file);
+        let document = self.document(file, &note);
+
+        document.write_head(out)?;
+        let mut diagnostics = self.write_body(note, file, out)?;
+        document.write_tail(out)?;
+
+        diagnostics.extend(document.diagnostics);
+        Ok(diagnostics)
+    }
+
+    /// Parse a note, and work out the options it's rendered with from its `gh-canvas:`
+    /// settings
+    fn parse_note(&self, markdown: &str, file: &Path) -> ParsedNote {
+        let mut note = ParsedNote {
+            ast: None,
+            options: self.options.clone(),
+            frontmatter: None,
+            diagnostics: Vec::new(),
+        };
+        let mut diagnose = |severity, position, message| {
+            note.diagnostics.push(Diagnostic {
+                severity,
+                message,
+                file: file.to_path_buf(),
+                position,
+            })
+        };
+
+        let ast = match ast_to_html::parse_markdown(markdown) {
+            Ok(ast) => ast,
+            Err(e) => {
+                diagnose(
+                    Severity::Error,
+                    None,
+                    format!("Couldn't parse Markdown: {e}"),
+                );
+                return note;
+            }
+        };
+
+        let frontmatter = ast_to_html::frontmatter(&ast);
+        let settings = frontmatter.as_ref().and_then(|(frontmatter, position)| {
+            Some((frontmatter.get(SETTINGS_KEY)?.clone(), position.clone()))
+        });
+        if let Some((settings, position)) = settings {
+            match self.options.with_note_settings(settings) {
+                Ok((options, warnings)) => {
+                    for warning in warnings {
+                        diagnose(Severity::Warning, position.clone(), warning);
+                    }
+                    note.options = options;
+                }
+                Err(e) => diagnose(Severity::Warning, position, e),
+            }
+        }
+
+        note.ast = Some(ast);
+        note.frontmatter = frontmatter.map(|(frontmatter, _)| frontmatter);
+        note
+    }
+
+    fn write_body(
+        &self,
+        note: ParsedNote,
+        file: &Path,
+        out: &mut dyn Write,
+    ) -> io::Result<Vec<Diagnostic>> {
+        let ParsedNote {
+            ast,
+            options,
+            mut diagnostics,
+            ..
+        } = note;
+
+        if let Some(ast) = ast {
+            diagnostics.extend(ast_to_html::write_html(
+                ast,
+                &self.vault,
+                file,
+                &options,
+                self.handler.as_ref(),
+                out,
+            )?);
+        }
+
+        Ok(diagnostics)
+    }
+
+    /// Everything in the document but the note itself
+    fn document(&self, file: &Path, note: &ParsedNote) -> RenderedDocument {
+        let options = &note.options;
+        let mut diagnostics = Vec::new();
+        let mut diagnose = |severity, message: String| {
+            diagnostics.push(Diagnostic {
+                severity,
+                message,
+                file: file.to_path_buf(),
+                position: None,
+            })
+        };
+
+        let (mut head_assets, missing_assets) =
+            assets::head_assets(options.assets, options.math == MathMode::Script);
+        if !missing_assets.is_empty() {
+            diagnose(
+                Severity::Warning,
+                format!(
+                    "this build doesn't bundle the {}, so the document may not match the CDN version. Run fetch-assets.sh before building to bundle them",
+                    missing_assets.join(", ")
+                ),
+            );
+        }
+        if options.highlight == HighlightMode::Script {
+            head_assets += &format!(
+                "<!-- Prism (for syntax highlighting)! -->\n<script>{PRISM_JS}</script>\n"
+            );
+        }
+
+        let theme = options.theme.resolve(&self.vault);
+        let (header_footer, unknown) = header_footer_css(
+            &options.header,
+            &options.footer,
+            &options.page.margins,
+            theme,
+            options.header_footer_on_first_page,
+            note.frontmatter.as_ref(),
+            file,
+        );
+        if !unknown.is_empty() {
+            diagnose(
+                Severity::Warning,
+                format!(
+                    "nothing to put in the header or footer for {}",
+                    unknown
+                        .iter()
+                        .map(|name| format!("{{{name}}}"))
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                ),
+            );
+        }
+
+        let StyleSettingsCss {
+            theme_css,
+            style_overrides,
+            body_classes,
+        } = self
+            .vault
+            .style_css(&theme)
+            .unwrap_or_else(|e| {
+                diagnose(
+                    Severity::Error,
+                    format!("Couldn't load the vault's theme: {e}"),
+                );
+                None
+            })
+            // Without a theme of its own, the document still needs the mode's class
+            .unwrap_or_else(|| StyleSettingsCss {
+                body_classes: theme.classname().to_string(),
+                ..Default::default()
+            });
+
+        RenderedDocument {
+            body: String::new(),
+            css: DocumentCss {
+                app: APP_CSS,
+                theme: theme_css,
+                style_overrides,
+                properties: PROPERTIES_CSS,
+                print: print_css(options, theme, &header_footer),
+            },
+            head_assets,
+            body_classes,
+            body_style: format!(
+                "--font-text-size: {}px; --zoom-factor: {}; --font-monospace-override: \"{}\";",
+                options.font_size, options.zoom_factor, options.mono_font
+            ),
+            diagnostics,
+        }
+    }
+}
+
+/// A parsed note, and the options it's rendered with
+struct ParsedNote {
+    /// `None` if the note couldn't be parsed
+    ast: Option<Node>,
+    options: RenderOptions,
+    frontmatter: Option<Value>,
+    diagnostics: Vec<Diagnostic>,
+}
+
+fn read_note(file: &Path) -> Result<(String, PathBuf), RenderError> {
+    let file = std::fs::canonicalize(file).unwrap_or(file.to_path_buf());
+
+    let markdown = std::fs::read_to_string(&file)
+        .map_err(|e| RenderError::new(&file, format!("Couldn't read Markdown: {e}")))?;
+
+    Ok((markdown, file))
+}
+
+impl RenderedDocument {
+    /// How many of the diagnostics are errors
+    pub fn error_count(&self) -> usize {
+        self.diagnostics
+            .iter()
+            .filter(|d| d.severity == Severity::Error)
+            .count()
+    }
+
+    /// The note as a standalone HTML document, ready for Chrome to print
+    pub fn to_html(&self) -> String {
+        let mut html = Vec::new();
+        self.write_html(&mut html)
+            .expect("writing to a Vec can't fail");
+
+        String::from_utf8_lossy(&html).into_owned()
+    }
+
+    /// Write the note out as a standalone HTML document
+    pub fn write_html(&self, out: &mut dyn Write) -> io::Result<()> {
+        self.write_head(out)?;
+        out.write_all(self.body.as_bytes())?;
+        self.write_tail(out)
+    }
+
+    /// Everything up to where the body goes
+    fn write_head(&self, out: &mut dyn Write) -> io::Result<()> {
+        let DocumentCss {
+            app,
+            theme,
+            style_overrides,
+            properties,
+            print,
+        } = &self.css;
+        let head_assets = &self.head_assets;
+        let body_classes = &self.body_classes;
+        let body_style = escape_html_str(&self.body_style);
+
+        write!(
+            out,
+            r#"<!DOCTYPE html>
+    <html>
+    <head>
+        <meta charset="UTF-8"/>
+        <style>
+            {app}
+        </style>
+        <style>
+            {theme}
+        </style>
+        <style>
+            {style_overrides}
+        </style>
+
+        <style>
+            {properties}
+        </style>
+
+        <style>
+            {print}
+        </style>
+
+        {head_assets}
+    </head>
+    <body class='{body_classes}' style="{body_style}">
+        <div class="print">
+            <div class="markdown-rendered markdown-preview-view show-properties">
+                "#
+        )
+    }
+
+    fn write_tail(&self, out: &mut dyn Write) -> io::Result<()> {
+        write!(
+            out,
+            r#"
+            </main>
+        </div>
+    </body>
+    </html>
+    "#
+        )
+    }
+}
+
+fn print_css(options: &RenderOptions, theme: ObsidianTheme, header_footer: &str) -> String {
+    let RenderOptions {
+        h1_weight,
+        h2_weight,
+        page,
+        header_footer_on_first_page,
+        page_break_before_h1,
+        ..
+    } = options;
+    // Light pages are printed on white whatever the theme's background, and dark ones
+    // keep their background, which browsers leave out of print by default
+    let background = match theme {
+        ObsidianTheme::Light => "--background-primary: #fff !important;",
+        ObsidianTheme::Dark => {
+            "print-color-adjust: exact;\n                -webkit-print-color-adjust: exact;"
+        }
+    };
+    let theme = theme.classname();
+    let Margins {
+        top,
+        right,
+        bottom,
+        left,
+    } = &page.margins;
+    let page_size = page.css_size(page.orientation);
+    let portrait_size = page.css_size(Orientation::Portrait);
+    let landscape_size = page.css_size(Orientation::Landscape);
+    // The properties block runs to the top of the first page, unless the header's there
+    let first_page_margin = match header_footer_on_first_page {
+        true => top.to_string(),
+        false => "0".to_string(),
+    };
+    // Not the first, which would leave the properties block on a page by itself
+    let h1_break = match page_break_before_h1 {
+        true => "page",
+        false => "auto",
+    };
+
+    format!(
+        r#"
+            :root {{
+                overflow: unset;
+            }}
+            .markdown-preview-view {{
+                overflow: unset;
+            }}
+            body {{
+                overflow: unset;
+                --file-margins: 0;
+                {background}
+            }}
+            body.{theme} {{
+                --h1-weight: {h1_weight};
+                --h2-weight: {h2_weight};
+            }}
+
+            @page {{
+                margin: 0;
+                margin-bottom: {bottom};
+                margin-top: {top};
+                padding: 0;
+                size: {page_size};
+            }}
+            @page portrait {{
+                size: {portrait_size};
+            }}
+            @page landscape {{
+                size: {landscape_size};
+            }}
+            .portrait {{
+                page: portrait;
+            }}
+            .landscape {{
+                page: landscape;
+            }}
+            @page:first {{
+                margin-top: {first_page_margin}
+            }}
+            {header_footer}
+            .non-meta-content {{
+                margin: 0;
+                margin-left: {left};
+                margin-right: {right};
+                margin-top: {top};
+            }}
+            .properties ~ .non-meta-content {{
+                margin-top: var(--spacing-p);
+            }}
+            .cover-page {{
+                break-after: page;
+                padding: 2.5in {right} 0 {left};
+            }}
+            .cover-page ~ .non-meta-content {{
+                margin-top: 0;
+            }}
+            .cover-plain, .cover-apa {{
+                text-align: center;
+            }}
+            .cover-plain .cover-title {{
+                font-size: 2em;
+                font-weight: var(--h1-weight);
+            }}
+            .cover-apa .cover-title {{
+                font-weight: bold;
+                margin-bottom: 2em;
+            }}
+            .cover-mla {{
+                padding: {top} {right} 0 {left};
+            }}
+            .cover-mla p {{
+                margin: 0;
+            }}
+            .cover-mla .cover-title {{
+                margin-top: var(--spacing-p);
+                text-align: center;
+            }}
+            .cover-mla ~ .non-meta-content {{
+                margin-top: var(--spacing-p);
+            }}
+            .page-break {{
+                break-after: page;
+            }}
+            .non-meta-content > h1:not(:first-child),
+            .markdown-embed > h1:not(:first-child),
+            .non-meta-content > .markdown-embed:not(:first-child) > h1:first-child {{
+                break-before: {h1_break};
+            }}
+            h1, h2, h3, h4, h5, h6 {{
+                break-after: avoid;
+                break-inside: avoid;
+            }}
+            pre, figure, img {{
+                break-inside: avoid;
+            }}
+            thead {{
+                display: table-header-group;
+            }}
+            tr {{
+                break-inside: avoid;
+            }}
+        "#
+    )
+}
@@ -0,0 +1,386 @@
+use std::{collections::HashSet, sync::OnceLock};
+
+use clap::ValueEnum;
+use regex::Regex;
+use serde::Deserialize;
+
+use crate::ast_to_html::escape_html_str;
+
+/// What happens to HTML written straight into a note
+#[derive(ValueEnum, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum HtmlMode {
+    /// Copy it into the document as it is, scripts and all
+    Keep,
+    /// Keep only the tags and attributes on the allow-list, for notes we didn't write
+    Sanitize,
+}
+
+/// The tags and attributes raw HTML can keep when it's sanitised
+#[derive(Clone, Debug)]
+pub struct HtmlAllowList {
+    pub tags: HashSet<String>,
+    pub attributes: HashSet<String>,
+}
+
+const DEFAULT_TAGS: &[&str] = &[
+    "a",
+    "abbr",
+    "b",
+    "blockquote",
+    "br",
+    "caption",
+    "center",
+    "cite",
+    "code",
+    "col",
+    "colgroup",
+    "dd",
+    "del",
+    "details",
+    "dfn",
+    "div",
+    "dl",
+    "dt",
+    "em",
+    "figcaption",
+    "figure",
+    "h1",
+    "h2",
+    "h3",
+    "h4",
+    "h5",
+    "h6",
+    "hr",
+    "i",
+    "img",
+    "ins",
+    "kbd",
+    "li",
+    "mark",
+    "ol",
+    "p",
+    "pre",
+    "q",
+    "s",
+    "samp",
+    "small",
+    "span",
+    "strong",
+    "sub",
+    "summary",
+    "sup",
+    "table",
+    "tbody",
+    "td",
+    "tfoot",
+    "th",
+    "thead",
+    "tr",
+    "u",
+    "ul",
+    "var",
+    "wbr",
+];
+
+const DEFAULT_ATTRIBUTES: &[&str] = &[
+    "align", "alt", "cite", "class", "colspan", "datetime", "dir", "height", "href", "id", "lang",
+    "open", "reversed", "rowspan", "scope", "src", "start", "title", "type", "width",
+];
+
+/// Tags whose contents go along with them, since they aren't text meant for the reader
+const DROP_CONTENT_TAGS: &[&str] = &[
+    "script", "style", "iframe", "object", "embed", "template", "noscript", "textarea", "title",
+    "frameset",
+];
+
+/// Attributes holding a URL, which can't be allowed to run script
+const URL_ATTRIBUTES: &[&str] = &[
+    "href",
+    "src",
+    "cite",
+    "action",
+    "formaction",
+    "poster",
+    "background",
+    "xlink:href",
+];
+
+impl Default for HtmlAllowList {
+    fn default() -> Self {
+        HtmlAllowList {
+            tags: DEFAULT_TAGS.iter().map(|tag| tag.to_string()).collect(),
+            attributes: DEFAULT_ATTRIBUTES.iter().map(|a| a.to_string()).collect(),
+        }
+    }
+}
+
+/// Raw HTML with everything off the allow-list taken out, and the names of what was taken
+/// out. Tags that aren't allowed are dropped but their text is kept, except for tags like
+/// `<script>` whose contents go too. Event handler attributes and `javascript:` URLs are
+/// never kept, whatever the allow-list says.
+pub fn sanitize_html(html: &str, allowed: &HtmlAllowList) -> (String, Vec<String>) {
+    static TOKEN: OnceLock<Regex> = OnceLock::new();
+    let token = TOKEN.get_or_init(|| {
+        Regex::new(
+            r#"(?s)<!--.*?(?:-->|$)|<!.*?>|<\?.*?>|<(/?)([A-Za-z][A-Za-z0-9:-]*)((?:\s+[^\s"'>/=]+(?:\s*=\s*(?:"[^"]*"|'[^']*'|[^\s"'=<>`]+))?)*)\s*(/?)>"#,
+        )
+        .expect("HTML token regex is valid")
+    });
+
+    let mut result = String::new();
+    let mut removed = Vec::new();
+    let mut remove = |what: String| {
+        if !removed.contains(&what) {
+            removed.push(what);
+        }
+    };
+    let mut rest = html;
+
+    while let Some(captures) = token.captures(rest) {
+        let whole = captures.get(0).expect("the whole match is always there");
+        result += &escape_text(&rest[..whole.start()]);
+        rest = &rest[whole.end()..];
+
+        let Some(name) = captures.get(2) else {
+            // Comments, doctypes and processing instructions don't show up anyway
+            continue;
+        };
+        let name = name.as_str().to_lowercase();
+        let closing = !captures[1].is_empty();
+
+        if DROP_CONTENT_TAGS.contains(&name.as_str()) && !allowed.tags.contains(&name) {
+            remove(format!("<{name}>"));
+            if !closing {
+                rest = after_closing_tag(rest, &name);
+            }
+            continue;
+        }
+        if !allowed.tags.contains(&name) {
+            remove(format!("<{name}>"));
+            continue;
+        }
+        if closing {
+            result += &format!("</{name}>");
+            continue;
+        }
+
+        result += &format!("<{name}");
+        for (attribute, value) in attributes(&captures[3]) {
+            let attribute = attribute.to_lowercase();
+            let value = value.map(decode_entities);
+
+            let safe = allowed.attributes.contains(&attribute)
+                && !attribute.starts_with("on")
+                && !(URL_ATTRIBUTES.contains(&attribute.as_str())
+                    && value.as_deref().is_some_and(|url| !is_safe_url(url)));
+            if !safe {
+                remove(format!("{attribute}="));
+                continue;
+            }
+
+            match value {
+                Some(value) => result += &format!(r#" {attribute}="{}""#, escape_html_str(&value)),
+                None => result += &format!(" {attribute}"),
+            }
+        }
+        result += &captures[4];
+        result += ">";
+    }
+
+    result += &escape_text(rest);
+    (result, removed)
+}
+
+/// The HTML after a tag's closing tag, or nothing if it isn't closed
+fn after_closing_tag<'a>(html: &'a str, name: &str) -> &'a str {
+    let lowercase = html.to_lowercase();
+    let closing = format!("</{name}");
+
+    match lowercase.find(&closing) {
+        Some(start) => match html[start..].find('>') {
+            Some(end) => &html[start + end + 1..],
+            None => "",
+        },
+        None => "",
+    }
+}
+
+/// `name="value"` pairs from inside a tag
+fn attributes(html: &str) -> Vec<(&str, Option<&str>)> {
+    static ATTRIBUTE: OnceLock<Regex> = OnceLock::new();
+    let attribute = ATTRIBUTE.get_or_init(|| {
+        Regex::new(r#"([^\s"'>/=]+)(?:\s*=\s*(?:"([^"]*)"|'([^']*)'|([^\s"'=<>`]+)))?"#)
+            .expect("attribute regex is valid")
+    });
+
+    attribute
+        .captures_iter(html)
+        .map(|captures| {
+            let value = captures
+                .get(2)
+                .or_else(|| captures.get(3))
+                .or_else(|| captures.get(4))
+                .map(|value| value.as_str());
+            (captures.get(1).map_or("", |name| name.as_str()), value)
+        })
+        .collect()
+}
+
+/// Whether a URL is safe to keep in a document: it doesn't run script when followed.
+/// Images can still be data URIs.
+pub fn is_safe_url(url: &str) -> bool {
+    let url = url
+        .chars()
+        .filter(|c| !c.is_whitespace() && !c.is_control())
+        .collect::<String>()
+        .to_lowercase();
+
+    let Some((scheme, rest)) = url.split_once(':') else {
+        return true;
+    };
+    // A colon after a path, query or fragment has started isn't a scheme
+    if scheme.contains(['/', '?', '#']) {
+        return true;
+    }
+
+    match scheme {
+        "http" | "https" | "mailto" | "tel" | "obsidian" => true,
+        "data" => rest.starts_with("image/") && !rest.starts_with("image/svg"),
+        _ => false,
+    }
+}
+
+/// Text between tags, which may already have entities in it but shouldn't have any stray
+/// `<` that a browser could take for a tag
+fn escape_text(text: &str) -> String {
+    text.replace('<', "&lt;").replace('>', "&gt;")
+}
+
+/// Undo the entities an attribute value is most likely to have, so that escaping it again
+/// doesn't double them up. Numeric entities are decoded like a browser would, with or
+/// without the semicolon, so that `&#106;avascript:` is checked as the URL it really is.
+fn decode_entities(value: &str) -> String {
+    static NUMERIC: OnceLock<Regex> = OnceLock::new();
+    let numeric = NUMERIC.get_or_init(|| {
+        Regex::new(r"&#(?:[xX]([0-9A-Fa-f]+)|([0-9]+));?").expect("entity regex is valid")
+    });
+
+    let value = numeric.replace_all(value, |captures: &regex::Captures| {
+        let code = match (captures.get(1), captures.get(2)) {
+            (Some(hex), _) => u32::from_str_radix(hex.as_str(), 16).ok(),
+            (_, Some(decimal)) => decimal.as_str().parse().ok(),
+            _ => None,
+        };
+        code.and_then(char::from_u32)
+            .unwrap_or(char::REPLACEMENT_CHARACTER)
+            .to_string()
+    });
+
+    value
+        .replace("&quot;", "\"")
+        .replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&amp;", "&")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sanitize(html: &str) -> String {
+        sanitize_html(html, &HtmlAllowList::default()).0
+    }
+
+    #[test]
+    fn removes_scripts_with_their_contents() {
+        let (html, removed) = sanitize_html(
+            "a<script>alert(1)</script>b<SCRIPT src=x>alert(2)</script >c",
+            &HtmlAllowList::default(),
+        );
+        assert_eq!(html, "abc");
+        assert_eq!(removed, ["<script>"]);
+    }
+
+    #[test]
+    fn keeps_text_of_tags_off_the_allow_list() {
+        assert_eq!(sanitize("<blink>hi</blink>"), "hi");
+    }
+
+    #[test]
+    fn removes_event_handlers() {
+        assert_eq!(
+            sanitize(r#"<img src="a.png" onerror="alert(1)" OnLoad=alert(2)>"#),
+            r#"<img src="a.png">"#
+        );
+    }
+
+    #[test]
+    fn removes_event_handlers_even_when_allowed() {
+        let mut allowed = HtmlAllowList::default();
+        allowed.attributes.insert("onclick".to_string());
+        let (html, _) = sanitize_html(r#"<b onclick="alert(1)">x</b>"#, &allowed);
+        assert_eq!(html, "<b>x</b>");
+    }
+
+    #[test]
+    fn removes_javascript_urls() {
+        assert_eq!(
+            sanitize(r#"<a href="javascript:alert(1)">x</a>"#),
+            "<a>x</a>"
+        );
+        assert_eq!(
+            sanitize(r#"<a href=" JaVa	Script:alert(1)">x</a>"#),
+            "<a>x</a>"
+        );
+    }
+
+    #[test]
+    fn removes_entity_encoded_schemes() {
+        assert_eq!(
+            sanitize(r#"<a href="&#106;avascript:alert(1)">x</a>"#),
+            "<a>x</a>"
+        );
+        assert_eq!(
+            sanitize(r#"<a href="java&#x09;script:alert(1)">x</a>"#),
+            "<a>x</a>"
+        );
+        assert_eq!(
+            sanitize(r#"<a href="&#106&#97;vascript:alert(1)">x</a>"#),
+            "<a>x</a>"
+        );
+        // Without a real colon it's a relative URL, and the ampersand is escaped again
+        assert_eq!(
+            sanitize(r#"<a href="javascript&colon;alert(1)">x</a>"#),
+            r#"<a href="javascript&amp;colon;alert(1)">x</a>"#
+        );
+    }
+
+    #[test]
+    fn removes_svg_data_urls() {
+        assert_eq!(
+            sanitize(r#"<img src="data:image/svg+xml;base64,PHN2Zz4=">"#),
+            "<img>"
+        );
+        assert_eq!(
+            sanitize(r#"<img src="data:image/png;base64,iVBO">"#),
+            r#"<img src="data:image/png;base64,iVBO">"#
+        );
+        assert!(!is_safe_url("data:text/html,<script>alert(1)</script>"));
+    }
+
+    #[test]
+    fn escapes_attribute_values() {
+        assert_eq!(
+            sanitize(r#"<span title='a"b'>x</span>"#),
+            r#"<span title="a&quot;b">x</span>"#
+        );
+    }
+
+    #[test]
+    fn keeps_ordinary_urls() {
+        assert!(is_safe_url("https://example.com/a:b"));
+        assert!(is_safe_url("notes/page.md#heading:1"));
+        assert!(is_safe_url("mailto:someone@example.com"));
+    }
+}
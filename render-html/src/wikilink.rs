@@ -0,0 +1,103 @@
+/// One `[[target#subpath|alias]]` link, or a `![[...]]` embed, as written in a note
+pub struct Wikilink<'a> {
+    pub target: &'a str,
+    pub subpath: Option<&'a str>,
+    pub alias: Option<&'a str>,
+    pub embed: bool,
+    pub source: &'a str,
+    /// Byte offset of `source` in the text it was found in
+    pub offset: usize,
+}
+
+const ATTACHMENT_EXTENSIONS: [&str; 14] = [
+    "png", "jpg", "jpeg", "gif", "bmp", "svg", "webp", "avif", "pdf", "mp3", "wav", "mp4", "webm",
+    "ogg",
+];
+
+pub enum TextSegment<'a> {
+    Text(&'a str),
+    Wikilink(Wikilink<'a>),
+}
+
+impl Wikilink<'_> {
+    /// Whether the link points at a note rather than an attachment like an image
+    pub fn is_note(&self) -> bool {
+        let extension = std::path::Path::new(self.target)
+            .extension()
+            .map(|ext| ext.to_string_lossy().to_lowercase());
+
+        !extension.is_some_and(|ext| ATTACHMENT_EXTENSIONS.contains(&ext.as_str()))
+    }
+
+    /// The text Obsidian shows for the link: the alias if there is one, otherwise the
+    /// target with any heading path written as `Note > Heading`.
+    pub fn display_text(&self) -> String {
+        if let Some(alias) = self.alias {
+            return alias.to_string();
+        }
+
+        let subpath = self.subpath.map(|s| {
+            s.split('#')
+                .filter(|s| !s.is_empty())
+                .collect::<Vec<_>>()
+                .join(" > ")
+        });
+
+        match (self.target, subpath) {
+            ("", Some(subpath)) => subpath,
+            (target, Some(subpath)) => format!("{target} > {subpath}"),
+            (target, None) => target.to_string(),
+        }
+    }
+}
+
+pub fn split_wikilinks(text: &str) -> Vec<TextSegment<'_>> {
+    let mut segments = Vec::new();
+    let mut rest = text;
+
+    while let Some(open) = rest.find("[[") {
+        let Some(close) = rest[open..].find("]]").map(|i| i + open) else {
+            break;
+        };
+
+        let inner = &rest[open + 2..close];
+        if inner.contains('\n') || inner.contains("[[") {
+            segments.push(TextSegment::Text(&rest[..open + 2]));
+            rest = &rest[open + 2..];
+            continue;
+        }
+
+        let embed = rest[..open].ends_with('!');
+        let start = if embed { open - 1 } else { open };
+
+        if start > 0 {
+            segments.push(TextSegment::Text(&rest[..start]));
+        }
+
+        let (link, alias) = match inner.split_once('|') {
+            Some((link, alias)) => (link, Some(alias.trim())),
+            None => (inner, None),
+        };
+        let (target, subpath) = match link.split_once('#') {
+            Some((target, subpath)) => (target, Some(subpath.trim())),
+            None => (link, None),
+        };
+
+        segments.push(TextSegment::Wikilink(Wikilink {
+            target: target.trim(),
+            subpath,
+            alias,
+            embed,
+            source: &rest[start..close + 2],
+            offset: text.len() - rest.len() + start,
+        }));
+
+        rest = &rest[close + 2..];
+    }
+
+    if !rest.is_empty() {
+        segments.push(TextSegment::Text(rest));
+    }
+
+    segments
+}